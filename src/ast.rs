@@ -26,6 +26,15 @@ pub enum BinaryOperator {
     Le,
     Gt,
     Ge,
+    // short-circuiting, evaluating to the operand that decided the result
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone)]
+pub enum UnaryOperator {
+    Neg,
+    Not,
 }
 
 // StatementType enum
@@ -37,16 +46,103 @@ pub enum StatementType {
     Conditional,
 }
 
+// position in the source text. line and column are 1-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Location {
+    line: u32,
+    column: usize,
+    offset: usize,
+}
+
+impl Location {
+    pub fn new(line: u32, column: usize, offset: usize) -> Self {
+        Self {
+            line,
+            column,
+            offset,
+        }
+    }
+
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+// range of source text covered by a node (end is exclusive)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SourceSpan {
+    start: Location,
+    end: Location,
+}
+
+impl SourceSpan {
+    pub fn new(start: Location, end: Location) -> Self {
+        Self { start, end }
+    }
+
+    // span from the start of `first` to the end of `last`
+    pub fn merge(first: SourceSpan, last: SourceSpan) -> Self {
+        Self::new(first.start, last.end)
+    }
+
+    pub fn start(&self) -> Location {
+        self.start
+    }
+
+    pub fn end(&self) -> Location {
+        self.end
+    }
+}
+
 #[derive(Debug, Clone)]
-pub enum Statement {
+pub struct Statement {
+    kind: StatementKind,
+    span: SourceSpan,
+}
+
+impl Statement {
+    pub fn new(kind: StatementKind, span: SourceSpan) -> Self {
+        Self { kind, span }
+    }
+
+    pub fn kind(&self) -> &StatementKind {
+        &self.kind
+    }
+
+    pub fn span(&self) -> SourceSpan {
+        self.span
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum StatementKind {
     Assignment(AssignmentStatement),
     ObjectAssignment(ObjectAssignmentStatement),
+    AttributeAssignment(AttributeAssignmentStatement),
     Expression(Expression),
     Block(Vec<Statement>),
     Conditional(ConditionalStatement),
     While(WhileStatement),
+    For(ForStatement),
     FuncDef(FuncDefStatement),
+    ClassDef(ClassDefStatement),
     Return(ReturnStatement),
+    Break,
+    Continue,
 }
 
 #[derive(Debug, Clone)]
@@ -101,6 +197,35 @@ impl ObjectAssignmentStatement {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct AttributeAssignmentStatement {
+    object: Box<Expression>,
+    name: String,
+    expression: Box<Expression>,
+}
+
+impl AttributeAssignmentStatement {
+    pub fn new(object: Expression, name: String, expression: Expression) -> Self {
+        Self {
+            object: Box::new(object),
+            name,
+            expression: Box::new(expression),
+        }
+    }
+
+    pub fn object(&self) -> &Expression {
+        &self.object
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn expression(&self) -> &Expression {
+        &self.expression
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ConditionalStatement {
     cond: Box<Expression>,
@@ -161,15 +286,92 @@ impl WhileStatement {
     }
 }
 
+// `for name in iterable do ... end`
+#[derive(Debug, Clone)]
+pub struct ForStatement {
+    name: String,
+    iterable: Box<Expression>,
+    body: Box<Statement>,
+}
+
+impl ForStatement {
+    pub fn new(name: String, iterable: Expression, body: Statement) -> Self {
+        Self {
+            name,
+            iterable: Box::new(iterable),
+            body: Box::new(body),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn iterable(&self) -> &Expression {
+        &self.iterable
+    }
+
+    pub fn body(&self) -> &Statement {
+        &self.body
+    }
+}
+
+// a declared parameter. parameters after `*` or `*rest` are keyword-only
+// and cannot be passed by position.
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    name: String,
+    default: Option<Expression>,
+    keyword_only: bool,
+    // `*rest`, which collects the extra positional arguments into a list
+    rest: bool,
+}
+
+impl Parameter {
+    pub fn new(name: String, default: Option<Expression>, keyword_only: bool) -> Self {
+        Self {
+            name,
+            default,
+            keyword_only,
+            rest: false,
+        }
+    }
+
+    pub fn new_rest(name: String) -> Self {
+        Self {
+            name,
+            default: None,
+            keyword_only: false,
+            rest: true,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn default(&self) -> Option<&Expression> {
+        self.default.as_ref()
+    }
+
+    pub fn is_keyword_only(&self) -> bool {
+        self.keyword_only
+    }
+
+    pub fn is_rest(&self) -> bool {
+        self.rest
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FuncDefStatement {
     name: String,
-    params: Vec<String>,
+    params: Vec<Parameter>,
     body: Box<Statement>,
 }
 
 impl FuncDefStatement {
-    pub fn new(name: String, params: Vec<String>, body: Statement) -> Self {
+    pub fn new(name: String, params: Vec<Parameter>, body: Statement) -> Self {
         Self {
             name,
             params,
@@ -181,7 +383,7 @@ impl FuncDefStatement {
         &self.name
     }
 
-    pub fn params(&self) -> &[String] {
+    pub fn params(&self) -> &[Parameter] {
         &self.params
     }
 
@@ -190,6 +392,35 @@ impl FuncDefStatement {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct ClassDefStatement {
+    name: String,
+    parent: Option<Expression>,
+    methods: Vec<FuncDefStatement>,
+}
+
+impl ClassDefStatement {
+    pub fn new(name: String, parent: Option<Expression>, methods: Vec<FuncDefStatement>) -> Self {
+        Self {
+            name,
+            parent,
+            methods,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn parent(&self) -> Option<&Expression> {
+        self.parent.as_ref()
+    }
+
+    pub fn methods(&self) -> &[FuncDefStatement] {
+        &self.methods
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ReturnStatement {
     expression: Option<Box<Expression>>,
@@ -212,12 +443,45 @@ impl ReturnStatement {
 }
 
 #[derive(Debug, Clone)]
-pub enum Expression {
+pub struct Expression {
+    kind: ExpressionKind,
+    span: SourceSpan,
+}
+
+impl Expression {
+    pub fn new(kind: ExpressionKind, span: SourceSpan) -> Self {
+        Self { kind, span }
+    }
+
+    pub fn kind(&self) -> &ExpressionKind {
+        &self.kind
+    }
+
+    pub fn span(&self) -> SourceSpan {
+        self.span
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ExpressionKind {
     Binary(BinaryExpression),
+    Unary(UnaryExpression),
     Literal(LiteralExpression),
     FunCall(FunCallExpression),
     Index(IndexExpression),
+    Attribute(AttributeExpression),
+    // `super.name`, the method `name` looked up from the parent of the method's class
+    SuperAttribute(String),
+    Lambda(LambdaExpression),
     Name(NameExpression),
+    // string literal with `{expr}` parts, joined into one string at runtime
+    InterpolatedString(Vec<StringPart>),
+}
+
+#[derive(Debug, Clone)]
+pub enum StringPart {
+    Text(String),
+    Interpolation(Expression),
 }
 
 #[derive(Debug, Clone)]
@@ -249,25 +513,63 @@ impl BinaryExpression {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct UnaryExpression {
+    op: UnaryOperator,
+    operand: Box<Expression>,
+}
+
+impl UnaryExpression {
+    pub fn new(op: UnaryOperator, operand: Expression) -> Self {
+        Self {
+            op,
+            operand: Box::new(operand),
+        }
+    }
+
+    pub fn op(&self) -> &UnaryOperator {
+        &self.op
+    }
+
+    pub fn operand(&self) -> &Expression {
+        &self.operand
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum LiteralExpression {
+    Null,
+    Boolean(bool),
     Integer(i64),
     Float(f64),
     String(String),
-    List,
+    List(Vec<Expression>),
+    Tuple(Vec<Expression>),
+    Dict(Vec<(Expression, Expression)>),
 }
 
 #[derive(Debug, Clone)]
 pub struct FunCallExpression {
     callee: Box<Expression>,
-    args: Vec<Expression>,
+    args: Vec<Argument>,
+    // `name = value` arguments, which always follow the positional ones
+    kwargs: Vec<(String, Expression)>,
+}
+
+// a positional argument of a call
+#[derive(Debug, Clone)]
+pub enum Argument {
+    Value(Expression),
+    // `*items`, passing each element of a list or tuple as its own argument
+    Spread(Expression),
 }
 
 impl FunCallExpression {
-    pub fn new(callee: Expression, args: Vec<Expression>) -> Self {
+    pub fn new(callee: Expression, args: Vec<Argument>, kwargs: Vec<(String, Expression)>) -> Self {
         Self {
             callee: Box::new(callee),
             args,
+            kwargs,
         }
     }
 
@@ -275,9 +577,13 @@ impl FunCallExpression {
         &self.callee
     }
 
-    pub fn args(&self) -> &[Expression] {
+    pub fn args(&self) -> &[Argument] {
         &self.args
     }
+
+    pub fn kwargs(&self) -> &[(String, Expression)] {
+        &self.kwargs
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -317,3 +623,50 @@ impl IndexExpression {
         &self.arg
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct AttributeExpression {
+    object: Box<Expression>,
+    name: String,
+}
+
+impl AttributeExpression {
+    pub fn new(object: Expression, name: String) -> Self {
+        Self {
+            object: Box::new(object),
+            name,
+        }
+    }
+
+    pub fn object(&self) -> &Expression {
+        &self.object
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// anonymous function, `fn(params) do ... end`
+#[derive(Debug, Clone)]
+pub struct LambdaExpression {
+    params: Vec<Parameter>,
+    body: Box<Statement>,
+}
+
+impl LambdaExpression {
+    pub fn new(params: Vec<Parameter>, body: Statement) -> Self {
+        Self {
+            params,
+            body: Box::new(body),
+        }
+    }
+
+    pub fn params(&self) -> &[Parameter] {
+        &self.params
+    }
+
+    pub fn body(&self) -> &Statement {
+        &self.body
+    }
+}
@@ -0,0 +1,57 @@
+use std::fmt;
+
+use crate::ast::SourceSpan;
+
+// error in a program that parsed but cannot be compiled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    message: String,
+    span: SourceSpan,
+}
+
+impl CompileError {
+    pub fn new(message: impl Into<String>, span: SourceSpan) -> Self {
+        Self {
+            message: message.into(),
+            span,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn span(&self) -> SourceSpan {
+        self.span
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.span.start())
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+// error raised while resolving jump labels into addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkError {
+    DuplicateLabel(String),
+    UnresolvedLabel(String),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::DuplicateLabel(label) => {
+                write!(f, "label `{}` is defined more than once", label)
+            }
+            LinkError::UnresolvedLabel(label) => {
+                write!(f, "jump to undefined label `{}`", label)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
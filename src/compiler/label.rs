@@ -0,0 +1,25 @@
+// hands out jump labels that are unique across all units of a program
+#[derive(Debug, Default)]
+pub struct LabelAllocator {
+    next_index: u32,
+}
+
+impl LabelAllocator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // label for a jump target inside a function
+    pub fn unique(&mut self) -> String {
+        let label = format!("L{}", self.next_index);
+        self.next_index += 1;
+        label
+    }
+
+    // label for the start of a function body. the name only makes code dumps readable.
+    pub fn function(&mut self, func_name: &str) -> String {
+        let label = format!("F{}#{}", func_name, self.next_index);
+        self.next_index += 1;
+        label
+    }
+}
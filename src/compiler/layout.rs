@@ -1,3 +1,5 @@
+use crate::opcode::Capture;
+
 #[derive(Debug, Clone)]
 pub struct LayoutTracker {
     locals: Vec<(String, usize)>,
@@ -24,7 +26,49 @@ impl LayoutTracker {
         None
     }
 
+    // slots are handed out in order, so this is also the next slot to be registered
+    pub fn n_locals(&self) -> usize {
+        self.locals.len()
+    }
+
     pub fn new() -> Self {
         Self { locals: Vec::new() }
     }
 }
+
+// variables of one function being compiled: its locals and
+// the upvalues it captures from enclosing functions
+#[derive(Debug, Clone)]
+pub struct FunctionScope {
+    pub layout: LayoutTracker,
+    pub upvalues: Vec<Capture>,
+}
+
+impl FunctionScope {
+    pub fn new() -> Self {
+        Self {
+            layout: LayoutTracker::new(),
+            upvalues: Vec::new(),
+        }
+    }
+
+    fn add_upvalue(&mut self, capture: Capture) -> usize {
+        if let Some(i) = self.upvalues.iter().position(|c| *c == capture) {
+            return i;
+        }
+        self.upvalues.push(capture);
+        self.upvalues.len() - 1
+    }
+}
+
+// resolves `name` as an upvalue of the last scope, capturing it through every
+// function in between. the first scope is the global one and never has locals.
+pub fn resolve_upvalue(scopes: &mut [FunctionScope], name: &str) -> Option<usize> {
+    let (current, outer) = scopes.split_last_mut()?;
+    let parent = outer.last()?;
+    if let Some(local) = parent.layout.get_local(name) {
+        return Some(current.add_upvalue(Capture::Local(local)));
+    }
+    let upvalue = resolve_upvalue(outer, name)?;
+    Some(current.add_upvalue(Capture::Upvalue(upvalue)))
+}
@@ -0,0 +1,25 @@
+use crate::ast::SourceSpan;
+
+// maps each linked instruction (by pc) to the source span it was compiled from
+#[derive(Debug, Clone, Default)]
+pub struct LineTable {
+    spans: Vec<Option<SourceSpan>>,
+}
+
+impl LineTable {
+    pub fn new(spans: Vec<Option<SourceSpan>>) -> Self {
+        Self { spans }
+    }
+
+    pub fn lookup(&self, pc: usize) -> Option<SourceSpan> {
+        self.spans.get(pc).copied().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+}
@@ -1,40 +1,89 @@
+mod error;
 mod global;
+mod label;
 mod layout;
+mod line_table;
 
 use std::collections::HashMap;
 
 use crate::{
-    ast::{Expression, LiteralExpression, Statement},
-    opcode::Opcode,
+    ast::{
+        Argument, BinaryOperator, Expression, ExpressionKind, FuncDefStatement, LiteralExpression,
+        Parameter, SourceSpan, Statement, StatementKind, StringPart, UnaryOperator,
+    },
+    object::Signature,
+    opcode::{Capture, Opcode},
 };
 
-use self::layout::LayoutTracker;
+pub use self::error::{CompileError, LinkError};
+use self::label::LabelAllocator;
+use self::layout::{resolve_upvalue, FunctionScope, LayoutTracker};
+pub use self::line_table::LineTable;
+
+// linked bytecode together with the source span of each instruction
+#[derive(Debug, Clone)]
+pub struct Program {
+    code: Vec<Opcode>,
+    line_table: LineTable,
+}
+
+impl Program {
+    pub fn code(&self) -> &[Opcode] {
+        &self.code
+    }
+
+    pub fn line_table(&self) -> &LineTable {
+        &self.line_table
+    }
+
+    pub fn into_parts(self) -> (Vec<Opcode>, LineTable) {
+        (self.code, self.line_table)
+    }
+}
 
 #[derive(Debug)]
 pub struct Compiler {
     codes: Vec<Vec<OpcodeWithMetadata>>,
+    labels: LabelAllocator,
 }
 
 impl Compiler {
     pub fn new() -> Self {
-        Self { codes: Vec::new() }
+        Self {
+            codes: Vec::new(),
+            labels: LabelAllocator::new(),
+        }
     }
 
-    pub fn compile_top(&mut self, top_stmt: &Statement) {
+    // compiles all top-level statements of a script into one program
+    pub fn compile_top(&mut self, program: &[Statement]) -> Result<(), Vec<CompileError>> {
         let mut unit_compiler = UnitCompiler::new(true);
-        unit_compiler.compile_stmt(top_stmt, &vec![]);
+        unit_compiler.labels = std::mem::take(&mut self.labels);
+        for stmt in program.iter() {
+            unit_compiler.compile_stmt(stmt, &vec![]);
+        }
+        self.labels = std::mem::take(&mut unit_compiler.labels);
+        if !unit_compiler.errors.is_empty() {
+            return Err(unit_compiler.errors);
+        }
         self.codes.extend(unit_compiler.collect_codes());
+        Ok(())
     }
 
-    fn link_jumps(&mut self, orig_codes: &Vec<OpcodeWithMetadata>) -> Vec<OpcodeWithMetadata> {
+    fn link_jumps(
+        &mut self,
+        orig_codes: &Vec<OpcodeWithMetadata>,
+    ) -> Result<Vec<OpcodeWithMetadata>, LinkError> {
         let mut codes = orig_codes.clone();
 
-        let mut label_map: HashMap<String, u32> = HashMap::new();
+        let mut label_map: HashMap<String, usize> = HashMap::new();
         // first pass: collect labels and their addresses
         for (i, op) in codes.iter().enumerate() {
             let labels = op.get_labels();
             for label in labels.iter() {
-                label_map.insert(label.clone(), i as u32);
+                if label_map.insert(label.clone(), i).is_some() {
+                    return Err(LinkError::DuplicateLabel(label.clone()));
+                }
             }
         }
 
@@ -45,76 +94,158 @@ impl Compiler {
             let jmp_to_label = op.get_jmp_to_label();
             if let Some(jmp_to_label) = jmp_to_label {
                 println!("processing: {}", jmp_to_label.as_str());
+                let jmp_to_addr = match label_map.get(&jmp_to_label) {
+                    Some(addr) => *addr,
+                    None => return Err(LinkError::UnresolvedLabel(jmp_to_label)),
+                };
                 match op.op {
                     Opcode::JmpIfTrue(_) => {
-                        let jmp_to_addr = label_map.get(&jmp_to_label).unwrap();
-                        op.op = Opcode::JmpIfTrue(*jmp_to_addr as usize);
+                        op.op = Opcode::JmpIfTrue(jmp_to_addr);
                     }
                     Opcode::JmpAlways(_) => {
-                        let jmp_to_addr = label_map.get(&jmp_to_label).unwrap();
-                        op.op = Opcode::JmpAlways(*jmp_to_addr as usize);
+                        op.op = Opcode::JmpAlways(jmp_to_addr);
                     }
                     Opcode::JmpIfFalse(_) => {
-                        let jmp_to_addr = label_map.get(&jmp_to_label).unwrap();
-                        op.op = Opcode::JmpIfFalse(*jmp_to_addr as usize);
+                        op.op = Opcode::JmpIfFalse(jmp_to_addr);
                     }
-                    Opcode::CreateFunction(_, n) => {
-                        let jmp_to_addr = label_map.get(&jmp_to_label).unwrap();
-                        op.op = Opcode::CreateFunction(*jmp_to_addr as usize, n);
+                    Opcode::ForIter(_) => {
+                        op.op = Opcode::ForIter(jmp_to_addr);
+                    }
+                    Opcode::CreateFunction(_, ref signature) => {
+                        op.op = Opcode::CreateFunction(jmp_to_addr, signature.clone());
                     }
                     _ => {}
                 }
             }
         }
 
-        codes
+        Ok(codes)
     }
 
-    pub fn link(&mut self) -> Vec<Opcode> {
+    pub fn link(&mut self) -> Result<Program, LinkError> {
         let concat_codes = self.codes.concat();
 
-        let linked = self.link_jumps(&concat_codes);
-        linked.iter().map(|op| op.op.clone()).collect()
+        let linked = self.link_jumps(&concat_codes)?;
+        Ok(Program {
+            code: linked.iter().map(|op| op.op.clone()).collect(),
+            line_table: LineTable::new(linked.iter().map(|op| op.get_span()).collect()),
+        })
     }
 }
 
 #[derive(Debug)]
 pub struct UnitCompiler {
     is_global: bool,
+    // method bodies hold their receiver in the first slot
+    is_method: bool,
     code: Vec<OpcodeWithMetadata>,
-    layout: LayoutTracker,
+    // scopes of the enclosing functions, innermost (this unit's) last
+    scopes: Vec<FunctionScope>,
 
     ext_codes: Vec<Vec<OpcodeWithMetadata>>,
 
-    current_label_index: u32,
+    labels: LabelAllocator,
+    // loops enclosing the code being compiled, innermost last
+    loops: Vec<LoopLabels>,
+    // span of the innermost node being compiled, attached to every emitted op
+    current_span: Option<SourceSpan>,
+    // errors of this unit and of the functions nested in it
+    errors: Vec<CompileError>,
+}
+
+// jump targets of a loop for `break` and `continue`
+#[derive(Debug)]
+struct LoopLabels {
+    continue_label: String,
+    break_label: String,
+    // `for` loops keep their iterator on the stack, which `break` has to drop
+    has_iterator: bool,
 }
 
 impl UnitCompiler {
     fn add_op(&mut self, op: Opcode) {
-        self.code.push(OpcodeWithMetadata::new_op(op));
+        self.add_op_md(op, Metadata::default());
     }
 
-    fn add_op_md(&mut self, op: Opcode, md: Metadata) {
+    fn add_op_md(&mut self, op: Opcode, mut md: Metadata) {
+        if md.span.is_none() {
+            md.span = self.current_span;
+        }
         self.code.push(OpcodeWithMetadata::new(op, md));
     }
 
     pub fn new(is_global: bool) -> UnitCompiler {
         Self {
             is_global,
+            is_method: false,
             code: Vec::new(),
-            layout: LayoutTracker::new(),
+            scopes: vec![FunctionScope::new()],
             ext_codes: Vec::new(),
-            current_label_index: 0,
+            labels: LabelAllocator::new(),
+            loops: Vec::new(),
+            current_span: None,
+            errors: Vec::new(),
         }
     }
 
     fn current_layout_mut(&mut self) -> &mut LayoutTracker {
-        &mut self.layout
+        &mut self.scopes.last_mut().unwrap().layout
+    }
+
+    // local variables of this function, then those of enclosing functions, then globals
+    fn resolve_name(&mut self, name: &str) -> Variable {
+        if self.is_global {
+            return Variable::Global;
+        }
+        if let Some(index) = self.current_layout_mut().get_local(name) {
+            return Variable::Local(index);
+        }
+        match resolve_upvalue(&mut self.scopes, name) {
+            Some(index) => Variable::Upvalue(index),
+            None => Variable::Global,
+        }
     }
 
     pub fn compile_expr(&mut self, expr: &Expression, top_labels: &Vec<String>) {
+        let outer_span = self.current_span.replace(expr.span());
+        self.compile_expr_kind(expr.kind(), top_labels);
+        self.current_span = outer_span;
+    }
+
+    fn compile_expr_kind(&mut self, expr: &ExpressionKind, top_labels: &Vec<String>) {
         match expr {
-            Expression::Binary(bin) => {
+            ExpressionKind::Binary(bin)
+                if matches!(bin.op(), BinaryOperator::And | BinaryOperator::Or) =>
+            {
+                // the left operand is kept as the result when it decides it,
+                // otherwise it is dropped and the right operand is evaluated
+                let end_label = self.generate_unique_label();
+                self.compile_expr(bin.left(), top_labels);
+                self.add_op(Opcode::Dup);
+                let jmp = match bin.op() {
+                    BinaryOperator::And => Opcode::JmpIfFalse(0),
+                    _ => Opcode::JmpIfTrue(0),
+                };
+                self.add_op_md(
+                    jmp,
+                    Metadata {
+                        this_label: vec![],
+                        jmp_to_label: Some(end_label.clone()),
+                        span: None,
+                    },
+                );
+                self.add_op(Opcode::Discard);
+                self.compile_expr(bin.right(), &vec![]);
+                self.add_op_md(
+                    Opcode::Nop,
+                    Metadata {
+                        this_label: vec![end_label],
+                        jmp_to_label: None,
+                        span: None,
+                    },
+                );
+            }
+            ExpressionKind::Binary(bin) => {
                 let op = bin.op();
                 let left = bin.left();
                 let right = bin.right();
@@ -136,115 +267,292 @@ impl UnitCompiler {
                     Le => Opcode::Le2,
                     Gt => Opcode::Gt2,
                     Ge => Opcode::Ge2,
+                    And | Or => unreachable!("logical operators are compiled to jumps"),
                 };
                 self.add_op(op);
             }
-            Expression::Literal(lit) => match lit {
+            ExpressionKind::InterpolatedString(parts) => {
+                // every part is converted to a string and added to the ones before it
+                for (i, part) in parts.iter().enumerate() {
+                    let labels = if i == 0 {
+                        top_labels.to_owned()
+                    } else {
+                        vec![]
+                    };
+                    match part {
+                        StringPart::Text(s) => self.add_op_md(
+                            Opcode::ConstString(s.to_string()),
+                            Metadata {
+                                this_label: labels,
+                                jmp_to_label: None,
+                                span: None,
+                            },
+                        ),
+                        StringPart::Interpolation(e) => {
+                            self.compile_expr(e, &labels);
+                            self.add_op(Opcode::ToStr);
+                        }
+                    }
+                    if i > 0 {
+                        self.add_op(Opcode::Add2);
+                    }
+                }
+            }
+            ExpressionKind::Unary(unary) => {
+                self.compile_expr(unary.operand(), top_labels);
+                let op = match unary.op() {
+                    UnaryOperator::Neg => Opcode::Neg,
+                    UnaryOperator::Not => Opcode::Not,
+                };
+                self.add_op(op);
+            }
+            ExpressionKind::Literal(lit) => match lit {
+                LiteralExpression::Null => {
+                    let md = Metadata {
+                        this_label: top_labels.to_owned(),
+                        jmp_to_label: None,
+                        span: None,
+                    };
+                    self.add_op_md(Opcode::ConstNull, md);
+                }
+                LiteralExpression::Boolean(b) => {
+                    let md = Metadata {
+                        this_label: top_labels.to_owned(),
+                        jmp_to_label: None,
+                        span: None,
+                    };
+                    self.add_op_md(Opcode::ConstBool(*b), md);
+                }
                 LiteralExpression::Integer(i) => {
                     let op = Opcode::ConstInt(*i);
                     let md = Metadata {
                         this_label: top_labels.to_owned(),
                         jmp_to_label: None,
+                        span: None,
                     };
                     self.add_op_md(op, md);
                 }
-                LiteralExpression::String(s) => {
-                    let op = Opcode::ConstString(s.to_string());
+                LiteralExpression::Float(f) => {
+                    let op = Opcode::ConstFloat(*f);
                     let md = Metadata {
                         this_label: top_labels.to_owned(),
                         jmp_to_label: None,
+                        span: None,
                     };
                     self.add_op_md(op, md);
                 }
-                _ => unimplemented!(),
-            },
-            Expression::Name(name) => {
-                let var_name = name.get_name();
-                if !self.is_global {
-                    let var_index = self.current_layout_mut().get_local(var_name);
-                    match var_index {
-                        None => {
-                            // fall back to global
-
-                            let op = Opcode::LoadGlobal(var_name.to_owned());
-
-                            let md = Metadata {
-                                this_label: top_labels.to_owned(),
-                                jmp_to_label: None,
-                            };
-                            self.add_op_md(op, md);
-                        }
-                        Some(var_index) => {
-                            let op = Opcode::Load(var_index);
-
-                            let md = Metadata {
-                                this_label: top_labels.to_owned(),
-                                jmp_to_label: None,
-                            };
-                            self.add_op_md(op, md);
-                        }
-                    };
-                } else {
-                    let op = Opcode::LoadGlobal(var_name.to_owned());
-
+                LiteralExpression::String(s) => {
+                    let op = Opcode::ConstString(s.to_string());
                     let md = Metadata {
                         this_label: top_labels.to_owned(),
                         jmp_to_label: None,
+                        span: None,
                     };
                     self.add_op_md(op, md);
                 }
+                LiteralExpression::List(elements) => {
+                    if elements.is_empty() {
+                        let md = Metadata {
+                            this_label: top_labels.to_owned(),
+                            jmp_to_label: None,
+                            span: None,
+                        };
+                        self.add_op_md(Opcode::BuildList(0), md);
+                    } else {
+                        for (i, element) in elements.iter().enumerate() {
+                            let v = vec![];
+                            self.compile_expr(element, if i == 0 { top_labels } else { &v });
+                        }
+                        self.add_op(Opcode::BuildList(elements.len()));
+                    }
+                }
+                LiteralExpression::Tuple(elements) => {
+                    if elements.is_empty() {
+                        let md = Metadata {
+                            this_label: top_labels.to_owned(),
+                            jmp_to_label: None,
+                            span: None,
+                        };
+                        self.add_op_md(Opcode::BuildTuple(0), md);
+                    } else {
+                        for (i, element) in elements.iter().enumerate() {
+                            let v = vec![];
+                            self.compile_expr(element, if i == 0 { top_labels } else { &v });
+                        }
+                        self.add_op(Opcode::BuildTuple(elements.len()));
+                    }
+                }
+                LiteralExpression::Dict(pairs) => {
+                    if pairs.is_empty() {
+                        let md = Metadata {
+                            this_label: top_labels.to_owned(),
+                            jmp_to_label: None,
+                            span: None,
+                        };
+                        self.add_op_md(Opcode::BuildDict(0), md);
+                    } else {
+                        for (i, (key, value)) in pairs.iter().enumerate() {
+                            let v = vec![];
+                            self.compile_expr(key, if i == 0 { top_labels } else { &v });
+                            self.compile_expr(value, &vec![]);
+                        }
+                        self.add_op(Opcode::BuildDict(pairs.len()));
+                    }
+                }
+            },
+            ExpressionKind::Name(name) => {
+                let var_name = name.get_name();
+                let op = match self.resolve_name(var_name) {
+                    Variable::Local(index) => Opcode::Load(index),
+                    Variable::Upvalue(index) => Opcode::LoadUpvalue(index),
+                    Variable::Global => Opcode::LoadGlobal(var_name.to_owned()),
+                };
+                let md = Metadata {
+                    this_label: top_labels.to_owned(),
+                    jmp_to_label: None,
+                    span: None,
+                };
+                self.add_op_md(op, md);
             }
-            Expression::FunCall(func) => {
+            ExpressionKind::FunCall(func) => {
                 let callee = func.callee();
                 let args = func.args();
 
                 // generate callee
                 self.compile_expr(callee, top_labels);
 
-                // generate args (from left to right)
+                // generate args (from left to right).
+                // with a spread argument, the count is only known at runtime, so all
+                // positional arguments are joined into one list
+                let spread = args.iter().any(|arg| matches!(arg, Argument::Spread(_)));
+                let mut n_pending = 0;
+                let mut n_parts = 0;
                 for arg in args.iter() {
-                    self.compile_expr(arg, &vec![]);
+                    match arg {
+                        Argument::Value(value) => {
+                            self.compile_expr(value, &vec![]);
+                            n_pending += 1;
+                        }
+                        Argument::Spread(items) => {
+                            if n_pending > 0 {
+                                self.add_op(Opcode::BuildList(n_pending));
+                                n_parts += 1;
+                                n_pending = 0;
+                            }
+                            self.compile_expr(items, &vec![]);
+                            n_parts += 1;
+                        }
+                    }
+                }
+                if spread {
+                    if n_pending > 0 {
+                        self.add_op(Opcode::BuildList(n_pending));
+                        n_parts += 1;
+                    }
+                    self.add_op(Opcode::ConcatLists(n_parts));
                 }
 
-                // generate call
-                let op = Opcode::CallNoKw(args.len());
+                // generate call. keyword arguments are passed as a dict after the others
+                let kwargs = func.kwargs();
+                for (name, value) in kwargs.iter() {
+                    self.add_op(Opcode::ConstString(name.to_string()));
+                    self.compile_expr(value, &vec![]);
+                }
+                if !kwargs.is_empty() {
+                    self.add_op(Opcode::BuildDict(kwargs.len()));
+                }
+                let op = match (spread, kwargs.is_empty()) {
+                    (true, no_kwargs) => Opcode::CallSpread(!no_kwargs),
+                    (false, true) => Opcode::CallNoKw(args.len()),
+                    (false, false) => Opcode::CallKw(args.len()),
+                };
                 self.add_op(op);
 
                 // generate return destination
                 self.add_op(Opcode::Nop);
             }
-            _ => unimplemented!(),
+            ExpressionKind::Index(index) => {
+                self.compile_expr(index.callee(), top_labels);
+                self.compile_expr(index.arg(), &vec![]);
+                self.add_op(Opcode::GetIndex);
+            }
+            ExpressionKind::Attribute(attr) => {
+                self.compile_expr(attr.object(), top_labels);
+                self.add_op(Opcode::GetAttr(attr.name().to_string()));
+            }
+            ExpressionKind::Lambda(lambda) => {
+                let label = self.generate_unique_label();
+                self.compile_function(label, lambda.params(), lambda.body(), false, top_labels);
+            }
+            ExpressionKind::SuperAttribute(name) => {
+                // the receiver is always in the first slot of a method. functions
+                // nested in a method have their own first slot, so they can't use super.
+                if !self.is_method {
+                    self.error("`super` used outside of a method");
+                }
+                self.add_op_md(
+                    Opcode::Load(0),
+                    Metadata {
+                        this_label: top_labels.to_owned(),
+                        jmp_to_label: None,
+                        span: None,
+                    },
+                );
+                self.add_op(Opcode::GetSuper(name.to_string()));
+            }
         }
     }
 
     pub fn compile_stmt(&mut self, stmt: &Statement, top_labels: &Vec<String>) {
         println!("compiling stmt: {:?}", stmt);
+        let outer_span = self.current_span.replace(stmt.span());
+        self.compile_stmt_kind(stmt.kind(), top_labels);
+        self.current_span = outer_span;
+    }
+
+    fn compile_stmt_kind(&mut self, stmt: &StatementKind, top_labels: &Vec<String>) {
         match stmt {
-            Statement::Expression(expr) => {
+            StatementKind::Expression(expr) => {
                 self.compile_expr(expr, top_labels);
                 let discard = Opcode::Discard;
                 self.add_op(discard);
             }
-            Statement::Assignment(assign) => {
+            StatementKind::Assignment(assign) => {
                 let name = assign.name();
 
                 self.compile_expr(assign.expression(), top_labels);
-                if !self.is_global {
-                    let assigned_index = self.current_layout_mut().register_local(name.to_string());
-                    let op = Opcode::Store(assigned_index);
-                    self.add_op(op);
-                } else {
-                    let op = Opcode::StoreGlobal(name.to_owned());
-                    self.add_op(op);
-                }
+                let op = self.store_op(name);
+                self.add_op(op);
+            }
+            StatementKind::ObjectAssignment(assign) => {
+                self.compile_expr(assign.object(), top_labels);
+                self.compile_expr(assign.index(), &vec![]);
+                self.compile_expr(assign.expression(), &vec![]);
+                self.add_op(Opcode::SetIndex);
+            }
+            StatementKind::AttributeAssignment(assign) => {
+                self.compile_expr(assign.object(), top_labels);
+                self.compile_expr(assign.expression(), &vec![]);
+                self.add_op(Opcode::SetAttr(assign.name().to_string()));
             }
-            Statement::Block(blk) => {
+            StatementKind::Block(blk) => {
+                if blk.is_empty() {
+                    // an empty block still needs an instruction to carry its labels
+                    self.add_op_md(
+                        Opcode::Nop,
+                        Metadata {
+                            this_label: top_labels.to_owned(),
+                            jmp_to_label: None,
+                            span: None,
+                        },
+                    );
+                }
                 for (i, stmt) in blk.iter().enumerate() {
                     let v = vec![];
                     self.compile_stmt(stmt, if i == 0 { top_labels } else { &v });
                 }
             }
-            Statement::Conditional(cond) => {
+            StatementKind::Conditional(cond) => {
                 // evaluate condition
                 let cond_expr = cond.cond();
                 self.compile_expr(cond_expr, top_labels);
@@ -256,32 +564,30 @@ impl UnitCompiler {
                     let md = Metadata {
                         this_label: vec![],
                         jmp_to_label: Some(true_label.clone()),
+                        span: None,
                     };
                     (op, md)
                 };
                 self.add_op_md(true_jmp_op.0, true_jmp_op.1);
 
-                // jump if false
-                let false_label = match cond.otherwise() {
-                    None => None,
-                    Some(_) => {
-                        let false_label = self.generate_unique_label();
-                        let false_jmp_op = {
-                            let op = Opcode::JmpAlways(0);
-                            let md = Metadata {
-                                this_label: vec![],
-                                jmp_to_label: Some(false_label.clone()),
-                            };
-                            (op, md)
-                        };
-                        self.add_op_md(false_jmp_op.0, false_jmp_op.1);
-                        Some(false_label)
-                    }
-                };
-
                 // branch end label
                 let branch_end_label = self.generate_unique_label();
 
+                // jump if false, to the else branch or past the whole statement
+                let false_label = cond.otherwise().map(|_| self.generate_unique_label());
+                self.add_op_md(
+                    Opcode::JmpAlways(0),
+                    Metadata {
+                        this_label: vec![],
+                        jmp_to_label: Some(
+                            false_label
+                                .clone()
+                                .unwrap_or_else(|| branch_end_label.clone()),
+                        ),
+                        span: None,
+                    },
+                );
+
                 // code for true branch
                 self.compile_stmt(cond.then(), &vec![true_label]);
                 self.add_op_md(
@@ -289,6 +595,7 @@ impl UnitCompiler {
                     Metadata {
                         this_label: vec![],
                         jmp_to_label: Some(branch_end_label.clone()),
+                        span: None,
                     },
                 );
 
@@ -303,48 +610,98 @@ impl UnitCompiler {
                     Metadata {
                         this_label: vec![branch_end_label],
                         jmp_to_label: None,
+                        span: None,
                     },
                 );
             }
-            Statement::While(wh) => {
+            StatementKind::While(wh) => {
                 let cond = wh.cond();
                 let body = wh.body();
 
                 let cond_label = self.generate_unique_label();
+                let iteration_end_label = self.generate_unique_label();
                 let body_end_label = self.generate_unique_label();
+                let first_slot = self.current_layout_mut().n_locals();
 
                 // evaluate condition
-                self.compile_expr(cond, &vec![cond_label.clone()]);
+                let cond_labels = [top_labels.to_owned(), vec![cond_label.clone()]].concat();
+                self.compile_expr(cond, &cond_labels);
                 // jump if condition is not met
                 self.add_op_md(
                     Opcode::JmpIfFalse(0),
                     Metadata {
                         this_label: vec![],
                         jmp_to_label: Some(body_end_label.clone()),
+                        span: None,
                     },
                 );
 
                 // generate body
+                self.loops.push(LoopLabels {
+                    continue_label: iteration_end_label.clone(),
+                    break_label: body_end_label.clone(),
+                    has_iterator: false,
+                });
                 self.compile_stmt(body, &vec![]);
+                self.loops.pop();
+                self.close_loop_variables(first_slot, iteration_end_label);
                 // jump back to condition
                 self.add_op_md(
                     Opcode::JmpAlways(0),
                     Metadata {
                         this_label: vec![],
                         jmp_to_label: Some(cond_label.clone()),
+                        span: None,
                     },
                 );
 
                 // end of while
+                self.close_loop_variables(first_slot, body_end_label);
+            }
+            StatementKind::For(for_stmt) => {
+                let next_label = self.generate_unique_label();
+                let iteration_end_label = self.generate_unique_label();
+                let end_label = self.generate_unique_label();
+                let first_slot = self.current_layout_mut().n_locals();
+
+                // the iterator stays on the stack while the loop runs
+                self.compile_expr(for_stmt.iterable(), top_labels);
+                self.add_op(Opcode::GetIter);
+                // push the next element, or pop the iterator and leave the loop
                 self.add_op_md(
-                    Opcode::Nop,
+                    Opcode::ForIter(0),
                     Metadata {
-                        this_label: vec![body_end_label],
-                        jmp_to_label: None,
+                        this_label: vec![next_label.clone()],
+                        jmp_to_label: Some(end_label.clone()),
+                        span: None,
                     },
                 );
+                let op = self.store_op(for_stmt.name());
+                self.add_op(op);
+
+                // generate body
+                self.loops.push(LoopLabels {
+                    continue_label: iteration_end_label.clone(),
+                    break_label: end_label.clone(),
+                    has_iterator: true,
+                });
+                self.compile_stmt(for_stmt.body(), &vec![]);
+                self.loops.pop();
+                self.close_loop_variables(first_slot, iteration_end_label);
+                // jump back for the next element
+                self.add_op_md(
+                    Opcode::JmpAlways(0),
+                    Metadata {
+                        this_label: vec![],
+                        jmp_to_label: Some(next_label),
+                        span: None,
+                    },
+                );
+
+                // end of for
+                self.close_loop_variables(first_slot, end_label);
             }
-            Statement::FuncDef(def) => {
+            StatementKind::FuncDef(def) => {
                 let func_name = def.name();
                 let func_params = def.params();
                 let func_body = def.body();
@@ -362,33 +719,88 @@ impl UnitCompiler {
                 };
 
                 let func_body_label = self.generate_func_label(func_name);
+                self.compile_function(func_body_label, func_params, func_body, false, top_labels);
 
-                // these codes are generated after the body of the currently compiling function
-                // so these are not the first instructions in the function
-                self.compile_fundef_body(
-                    func_params,
-                    func_body,
-                    &vec![vec![func_body_label.clone()]].concat(),
+                self.add_op(func_register_op);
+                println!(
+                    "registered function: {} (global: {})",
+                    func_name, self.is_global
                 );
+            }
+            StatementKind::ClassDef(class) => {
+                let class_name = class.name();
+                let methods = class.methods();
+
+                // the parent class (or null) is pushed first,
+                // then each method as its name followed by its function object
+                match class.parent() {
+                    Some(parent) => self.compile_expr(parent, top_labels),
+                    None => self.add_op_md(
+                        Opcode::ConstNull,
+                        Metadata {
+                            this_label: top_labels.to_owned(),
+                            jmp_to_label: None,
+                            span: None,
+                        },
+                    ),
+                }
+                for method in methods.iter() {
+                    self.compile_method(class_name, method);
+                }
+                self.add_op(Opcode::CreateClass(class_name.to_string(), methods.len()));
 
+                if !self.is_global {
+                    let class_index = self
+                        .current_layout_mut()
+                        .register_local(class_name.to_string());
+                    self.add_op(Opcode::Store(class_index));
+                } else {
+                    self.add_op(Opcode::StoreGlobal(class_name.to_string()));
+                }
+            }
+            StatementKind::Break | StatementKind::Continue => {
+                let is_break = matches!(stmt, StatementKind::Break);
+                let Some(target) = self.loops.last() else {
+                    let keyword = if is_break { "break" } else { "continue" };
+                    self.error(format!("`{}` outside of a loop", keyword));
+                    return;
+                };
+                let (label, drop_iterator) = match is_break {
+                    true => (target.break_label.clone(), target.has_iterator),
+                    false => (target.continue_label.clone(), false),
+                };
+                // the op carrying top_labels comes first
+                let mut this_label = top_labels.to_owned();
+                if drop_iterator {
+                    self.add_op_md(
+                        Opcode::Discard,
+                        Metadata {
+                            this_label: std::mem::take(&mut this_label),
+                            jmp_to_label: None,
+                            span: None,
+                        },
+                    );
+                }
                 self.add_op_md(
-                    Opcode::CreateFunction(0, func_params.len()),
+                    Opcode::JmpAlways(0),
                     Metadata {
-                        this_label: top_labels.to_owned(), // this is the first instruction in the function.
-                        jmp_to_label: Some(func_body_label.clone()),
+                        this_label,
+                        jmp_to_label: Some(label),
+                        span: None,
                     },
                 );
-
-                self.add_op(func_register_op);
-                println!(
-                    "registered function: {} (global: {})",
-                    func_name, self.is_global
-                );
             }
-            Statement::Return(ret) => {
+            StatementKind::Return(ret) => {
                 match ret.expression() {
                     None => {
-                        self.add_op(Opcode::ConstNull);
+                        self.add_op_md(
+                            Opcode::ConstNull,
+                            Metadata {
+                                this_label: top_labels.to_owned(),
+                                jmp_to_label: None,
+                                span: None,
+                            },
+                        );
                     }
                     Some(e) => {
                         self.compile_expr(e, top_labels);
@@ -396,21 +808,99 @@ impl UnitCompiler {
                 }
                 self.add_op(Opcode::Return);
             }
-            _ => unimplemented!(),
         }
     }
 
-    fn compile_fundef_body(
+    // records an error at the node being compiled. compilation goes on
+    // so that later errors are reported too.
+    fn error(&mut self, message: impl Into<String>) {
+        let span = self.current_span.unwrap_or_default();
+        self.errors.push(CompileError::new(message, span));
+    }
+
+    // instruction storing the value on top of the stack into `name`.
+    // assigning to a captured variable updates it in the enclosing function.
+    // any other name becomes a new local.
+    fn store_op(&mut self, name: &str) -> Opcode {
+        match self.resolve_name(name) {
+            Variable::Upvalue(index) => Opcode::StoreUpvalue(index),
+            Variable::Global if self.is_global => Opcode::StoreGlobal(name.to_owned()),
+            _ => Opcode::Store(self.current_layout_mut().register_local(name.to_string())),
+        }
+    }
+
+    // emits CreateFunction for a function whose body starts at `func_body_label`
+    fn compile_function(
         &mut self,
-        params: &[String],
+        func_body_label: String,
+        params: &[Parameter],
         body: &Statement,
+        is_method: bool,
         top_labels: &Vec<String>,
     ) {
+        // these codes are generated after the body of the currently compiling function
+        // so these are not the first instructions in the function
+        let captures =
+            self.compile_fundef_body(params, body, is_method, &vec![func_body_label.clone()]);
+
+        // default values are evaluated once, when the function is created
+        let mut top_labels = top_labels.to_owned();
+        for default in params.iter().filter_map(|p| p.default()) {
+            self.compile_expr(default, &std::mem::take(&mut top_labels));
+        }
+
+        // the parser keeps positional params first, then `*rest`, then keyword-only ones
+        let signature = Signature::new(
+            params.iter().map(|p| p.name().to_string()).collect(),
+            params
+                .iter()
+                .filter(|p| !p.is_keyword_only() && !p.is_rest())
+                .count(),
+            params.iter().any(|p| p.is_rest()),
+            params.iter().map(|p| p.default().is_some()).collect(),
+        );
+        self.add_op_md(
+            Opcode::CreateFunction(0, signature),
+            Metadata {
+                this_label: top_labels, // empty if the defaults already took the labels
+                jmp_to_label: Some(func_body_label),
+                span: None,
+            },
+        );
+        if !captures.is_empty() {
+            self.add_op(Opcode::MakeClosure(captures));
+        }
+    }
+
+    // methods take the receiver as an implicit first parameter named `self`
+    fn compile_method(&mut self, class_name: &str, method: &FuncDefStatement) {
+        self.add_op(Opcode::ConstString(method.name().to_string()));
+
+        let receiver = Parameter::new("self".to_string(), None, false);
+        let params = [vec![receiver], method.params().to_vec()].concat();
+        let label = self.generate_func_label(&format!("{}.{}", class_name, method.name()));
+        self.compile_function(label, &params, method.body(), true, &vec![]);
+    }
+
+    // compiles a function body into ext_codes and returns the variables it captures
+    fn compile_fundef_body(
+        &mut self,
+        params: &[Parameter],
+        body: &Statement,
+        is_method: bool,
+        top_labels: &Vec<String>,
+    ) -> Vec<Capture> {
         let mut unit = UnitCompiler::new(false);
+        unit.is_method = is_method;
+        unit.labels = std::mem::take(&mut self.labels);
+        // the nested unit sees the scopes of all enclosing functions
+        unit.scopes = std::mem::take(&mut self.scopes);
+        unit.scopes.push(FunctionScope::new());
 
         // register params in order
         for param in params.iter() {
-            unit.current_layout_mut().register_local(param.to_string());
+            unit.current_layout_mut()
+                .register_local(param.name().to_string());
         }
 
         unit.compile_stmt(body, top_labels);
@@ -419,8 +909,34 @@ impl UnitCompiler {
         unit.add_op(Opcode::ConstNull);
         unit.add_op(Opcode::Return);
 
+        let scope = unit.scopes.pop().unwrap();
+        self.scopes = std::mem::take(&mut unit.scopes);
+        self.labels = std::mem::take(&mut unit.labels);
+        self.errors.append(&mut unit.errors);
+
         let codes = unit.collect_codes();
         self.ext_codes.extend(codes);
+        scope.upvalues
+    }
+
+    // emits the op at `label` ending a loop iteration, or the loop itself. locals first
+    // assigned in the loop (from `first_slot` on) are closed, so that closures created
+    // in one iteration keep that iteration's values instead of sharing later ones.
+    fn close_loop_variables(&mut self, first_slot: usize, label: String) {
+        let slots: Vec<usize> = (first_slot..self.current_layout_mut().n_locals()).collect();
+        let op = if self.is_global || slots.is_empty() {
+            Opcode::Nop
+        } else {
+            Opcode::CloseUpvalues(slots)
+        };
+        self.add_op_md(
+            op,
+            Metadata {
+                this_label: vec![label],
+                jmp_to_label: None,
+                span: None,
+            },
+        );
     }
 
     // first code will always be a main code
@@ -437,17 +953,21 @@ impl UnitCompiler {
     }
 
     fn generate_unique_label(&mut self) -> String {
-        let label = format!("L{}", self.current_label_index);
-        self.current_label_index += 1;
-        label
+        self.labels.unique()
     }
 
     fn generate_func_label(&mut self, func_name: &str) -> String {
-        let label = format!("F{}", func_name);
-        label
+        self.labels.function(func_name)
     }
 }
 
+// where a name is stored, as seen from the function being compiled
+enum Variable {
+    Local(usize),
+    Upvalue(usize),
+    Global,
+}
+
 #[derive(Debug, Clone)]
 pub struct OpcodeWithMetadata {
     op: Opcode,
@@ -465,6 +985,7 @@ impl OpcodeWithMetadata {
             md: Metadata {
                 jmp_to_label: None,
                 this_label: vec![],
+                span: None,
             },
         }
     }
@@ -476,10 +997,15 @@ impl OpcodeWithMetadata {
     pub fn get_jmp_to_label(&self) -> Option<String> {
         self.md.jmp_to_label.clone()
     }
+
+    pub fn get_span(&self) -> Option<SourceSpan> {
+        self.md.span
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Metadata {
     pub jmp_to_label: Option<String>,
     pub this_label: Vec<String>,
+    pub span: Option<SourceSpan>,
 }
@@ -0,0 +1,231 @@
+use nom::error::{VerboseError, VerboseErrorKind};
+
+use crate::{
+    ast::Location,
+    compiler::CompileError,
+    parser::{is_keyword, Span},
+};
+
+// a parse error turned into a message for script authors
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    message: String,
+    location: Location,
+}
+
+impl Diagnostic {
+    pub fn new(message: String, location: Location) -> Self {
+        Self { message, location }
+    }
+
+    // builds a diagnostic from the context stack recorded by the parser.
+    // the innermost token or rule tells what was expected, and the nearest
+    // enclosing construct tells where the unfinished construct started.
+    pub fn from_parse_error(err: &VerboseError<Span>) -> Self {
+        let failed_at = match err.errors.first() {
+            Some((span, _)) => *span,
+            None => return Self::new("invalid syntax".to_string(), Location::new(1, 1, 0)),
+        };
+
+        let contexts: Vec<(Span, &str)> = err
+            .errors
+            .iter()
+            .filter_map(|(span, kind)| match kind {
+                VerboseErrorKind::Context(ctx) => Some((*span, *ctx)),
+                _ => None,
+            })
+            .collect();
+
+        let expected_index = contexts
+            .iter()
+            .position(|(_, ctx)| is_token(ctx) || expected_description(ctx).is_some());
+
+        // a keyword where a name is required gets its own message, as the
+        // keyword is valid syntax and would otherwise be reported as unexpected
+        let misused_keyword = match contexts.first() {
+            Some((span, "keyword_as_name")) => Some(*span),
+            Some((span, "ident")) if is_keyword(span.fragment()) => Some(*span),
+            _ => None,
+        };
+        if let Some(span) = misused_keyword {
+            let message = format!(
+                "`{}` is a keyword and cannot be used as a name",
+                span.fragment()
+            );
+            return Self::new(message, location_of(span));
+        }
+
+        // input that parses but is not allowed is reported on its own
+        let rejected = match contexts.first() {
+            Some((span, "integer_too_large")) => Some((
+                *span,
+                format!("integer literal `{}` is too large", span.fragment()),
+            )),
+            Some((span, "duplicate_parameter")) => Some((
+                *span,
+                format!("parameter `{}` is declared more than once", span.fragment()),
+            )),
+            Some((span, "duplicate_keyword_argument")) => Some((
+                *span,
+                format!(
+                    "keyword argument `{}` is given more than once",
+                    span.fragment()
+                ),
+            )),
+            Some((span, "bare_star")) => Some((
+                *span,
+                "`*` must be followed by a keyword-only parameter".to_string(),
+            )),
+            _ => None,
+        };
+        if let Some((span, message)) = rejected {
+            return Self::new(message, location_of(span));
+        }
+
+        let mut message = match expected_index {
+            Some(i) => {
+                let ctx = contexts[i].1;
+                if is_token(ctx) {
+                    format!("expected `{}`", ctx)
+                } else {
+                    format!("expected {}", expected_description(ctx).unwrap())
+                }
+            }
+            None => "invalid syntax".to_string(),
+        };
+
+        // a construct starting right where parsing failed is the one that could not
+        // begin (e.g. a missing `do`), so it is skipped in favor of its parent
+        let outer = &contexts[expected_index.map_or(0, |i| i + 1)..];
+        if let Some((start, ctx)) = outer.iter().find(|(span, ctx)| {
+            construct_description(ctx).is_some()
+                && span.location_offset() != failed_at.location_offset()
+        }) {
+            let closes = expected_index.is_some_and(|i| closing_token(ctx) == Some(contexts[i].1));
+            message.push_str(&format!(
+                " {} {} started at {}",
+                if closes { "to close" } else { "in" },
+                construct_description(ctx).unwrap(),
+                location_of(*start)
+            ));
+        }
+
+        message.push_str(&format!(", found {}", found_description(failed_at)));
+
+        Self::new(message, location_of(failed_at))
+    }
+
+    pub fn from_compile_error(err: &CompileError) -> Self {
+        Self::new(err.message().to_string(), err.span().start())
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn location(&self) -> Location {
+        self.location
+    }
+
+    // renders the message followed by the offending source line
+    // with a caret under the failing column
+    pub fn render(&self, source: &str) -> String {
+        let line_no = self.location.line();
+        let line = source.lines().nth(line_no as usize - 1).unwrap_or("");
+        let gutter = " ".repeat(line_no.to_string().len());
+        // keep tabs so that the caret lines up with the source line
+        let caret_pad: String = line
+            .chars()
+            .take(self.location.column() - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        format!(
+            "error: {}\n{}--> {}\n{} |\n{} | {}\n{} | {}^\n",
+            self.message, gutter, self.location, gutter, line_no, line, gutter, caret_pad
+        )
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}", self.message, self.location)
+    }
+}
+
+fn location_of(span: Span) -> Location {
+    Location::new(
+        span.location_line(),
+        span.get_utf8_column(),
+        span.location_offset(),
+    )
+}
+
+// keywords and punctuation are recorded by the parser under their own text
+fn is_token(ctx: &str) -> bool {
+    is_keyword(ctx) || !ctx.chars().any(|c| c.is_alphanumeric())
+}
+
+fn closing_token(construct: &str) -> Option<&'static str> {
+    match construct {
+        "block_stmt" | "classdef_stmt" => Some("end"),
+        "call_paren" | "paren expr" => Some(")"),
+        "index_paren" | "list_literal" => Some("]"),
+        "dict_literal" | "interpolation" => Some("}"),
+        "string_literal" => Some("\""),
+        "block_comment" => Some("]#"),
+        _ => None,
+    }
+}
+
+fn expected_description(ctx: &str) -> Option<&'static str> {
+    match ctx {
+        "ident" => Some("a name"),
+        "expression" => Some("an expression"),
+        "statement" => Some("a statement"),
+        "keyword_argument" => Some("a keyword argument"),
+        "escape_sequence" => Some("an escape sequence"),
+        "eof" => Some("end of input"),
+        _ => None,
+    }
+}
+
+fn construct_description(ctx: &str) -> Option<&'static str> {
+    match ctx {
+        "block_stmt" => Some("`do` block"),
+        "funcdef_stmt" => Some("function definition"),
+        "classdef_stmt" => Some("class definition"),
+        "lambda_expr" => Some("function expression"),
+        "conditional_stmt" => Some("`if` statement"),
+        "while_stmt" => Some("`while` loop"),
+        "for_stmt" => Some("`for` loop"),
+        "return_stmt" => Some("`return` statement"),
+        "param_list" => Some("parameter list"),
+        "call_paren" => Some("argument list"),
+        "index_paren" => Some("index"),
+        "list_literal" => Some("list literal"),
+        "dict_literal" => Some("dict literal"),
+        "string_literal" => Some("string literal"),
+        "interpolation" => Some("string interpolation"),
+        "paren expr" => Some("parenthesized expression"),
+        "block_comment" => Some("block comment"),
+        _ => None,
+    }
+}
+
+fn found_description(span: Span) -> String {
+    let rest: &str = span.fragment();
+    let rest = rest.trim_start_matches([' ', '\t']);
+    match rest.chars().next() {
+        None => "end of input".to_string(),
+        Some('\n') | Some('\r') => "end of line".to_string(),
+        Some(c) if c.is_alphanumeric() || c == '_' => {
+            let word: String = rest
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || *c == '_')
+                .collect();
+            format!("`{}`", word)
+        }
+        Some(c) => format!("`{}`", c),
+    }
+}
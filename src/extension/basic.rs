@@ -1,35 +1,154 @@
-use crate::{extension::NativeFunctionInfoBuilder, object::Value, vm::VM};
+use crate::{
+    extension::NativeFunctionInfoBuilder,
+    object::{internal::hashmap::HashMap as MyHashMap, is_subclass, Range, Value},
+    vm::{RuntimeError, RuntimeErrorKind, VM},
+};
 
-use super::{NativeFunctionInfo, RegisterableExtension};
+use super::{NativeFunctionInfo, NativeParam, RegisterableExtension};
 
-fn println_impl(vm: &mut VM) -> Value {
+// prints its arguments separated by spaces
+fn println_impl(vm: &mut VM) -> Result<Value, RuntimeError> {
+    let values = vm.get_function_argument_by_index(0);
+    let ending = vm.get_function_argument_by_index(1);
+    let ending = match ending.get().value() {
+        Value::String(ending) => ending.clone(),
+        v => {
+            let message = format!("`ending` must be a string, got {}", v.type_name());
+            return Err(vm.runtime_error(RuntimeErrorKind::InvalidOperands, message));
+        }
+    };
+    let parts = match values.get().value() {
+        Value::List(elements) => elements
+            .iter()
+            .map(|e| vm.display_string(e.get().value()))
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => unreachable!("rest arguments are passed as a list"),
+    };
+    print!("{}{}", parts.join(" "), ending);
+    Ok(Value::Null)
+}
+
+fn str_impl(vm: &mut VM) -> Result<Value, RuntimeError> {
     let arg = vm.get_function_argument_by_index(0);
-    let arg = arg.get();
-    match arg.value() {
-        Value::String(s) => println!("{}", s),
-        Value::Integer(i) => println!("{}", i),
-        Value::Boolean(b) => println!("{}", b),
-        Value::Invalid => panic!("access to uninitialized value"),
-        Value::Null => println!("null"),
-        Value::Function(_) => println!("<function object>"),
-        Value::Instance(_) => println!("<instance object>"),
-    }
-    Value::Null
+    let str_value = vm.display_string(arg.get().value())?;
+    Ok(Value::String(str_value))
 }
 
-fn str_impl(vm: &mut VM) -> Value {
+fn len_impl(vm: &mut VM) -> Result<Value, RuntimeError> {
     let arg = vm.get_function_argument_by_index(0);
-    let arg = arg.get();
-    let str_value = match arg.value() {
-        Value::String(s) => s.clone(),
-        Value::Integer(i) => i.to_string(),
-        Value::Boolean(b) => b.to_string(),
-        Value::Invalid => panic!("access to uninitialized value"),
-        Value::Null => "null".to_string(),
-        Value::Function(_) => "<function object>".to_string(),
-        Value::Instance(_) => "<instance object>".to_string(),
+    let len = match arg.get().value() {
+        Value::String(s) => s.chars().count(),
+        Value::List(elements) | Value::Tuple(elements) => elements.len(),
+        Value::Dict(map) => map.len(),
+        Value::Range(range) => range.len(),
+        v => {
+            return Err(vm.runtime_error(
+                RuntimeErrorKind::InvalidOperands,
+                format!("{} has no length", v.type_name()),
+            ))
+        }
+    };
+    Ok(Value::Integer(len as i64))
+}
+
+// runs `f` on the dict passed as the argument at `index`
+fn with_dict_argument<T>(
+    vm: &mut VM,
+    index: usize,
+    f: impl FnOnce(&MyHashMap) -> T,
+) -> Result<T, RuntimeError> {
+    let arg = vm.get_function_argument_by_index(index);
+    let result = match arg.get().value() {
+        Value::Dict(map) => Ok(f(map)),
+        v => Err(vm.runtime_error(
+            RuntimeErrorKind::InvalidOperands,
+            format!("expected a dict, got {}", v.type_name()),
+        )),
+    };
+    result
+}
+
+fn keys_impl(vm: &mut VM) -> Result<Value, RuntimeError> {
+    with_dict_argument(vm, 0, |map| {
+        Value::List(map.entries().into_iter().map(|(k, _)| k).collect())
+    })
+}
+
+fn values_impl(vm: &mut VM) -> Result<Value, RuntimeError> {
+    with_dict_argument(vm, 0, |map| {
+        Value::List(map.entries().into_iter().map(|(_, v)| v).collect())
+    })
+}
+
+fn has_impl(vm: &mut VM) -> Result<Value, RuntimeError> {
+    let key = vm.get_function_argument_by_index(1);
+    with_dict_argument(vm, 0, |map| match key.get().value().hash() {
+        Some(_) => Value::Boolean(map.exists(key.clone())),
+        // unhashable values can never be stored as keys
+        None => Value::Boolean(false),
+    })
+}
+
+// removes `key` from the dict and returns whether it was present
+fn remove_impl(vm: &mut VM) -> Result<Value, RuntimeError> {
+    let mut dict = vm.get_function_argument_by_index(0);
+    let key = vm.get_function_argument_by_index(1);
+    let removed = match (dict.get_mut().value_mut(), key.get().value().hash()) {
+        (Value::Dict(map), Some(_)) => map.remove(key.clone()),
+        (Value::Dict(_), None) => None,
+        (v, _) => {
+            let message = format!("expected a dict, got {}", v.type_name());
+            return Err(vm.runtime_error(RuntimeErrorKind::InvalidOperands, message));
+        }
+    };
+    Ok(Value::Boolean(removed.is_some()))
+}
+
+// `range(stop)`, `range(start, stop)` or `range(start, stop, step)`
+fn range_impl(vm: &mut VM) -> Result<Value, RuntimeError> {
+    let mut bounds = Vec::new();
+    for index in 0..3 {
+        let arg = vm.get_function_argument_by_index(index);
+        match arg.get().value() {
+            Value::Integer(i) => bounds.push(Some(*i)),
+            // only stop may be left out
+            Value::Null => bounds.push(None),
+            v => {
+                let message = format!("range bounds must be integers, got {}", v.type_name());
+                return Err(vm.runtime_error(RuntimeErrorKind::InvalidOperands, message));
+            }
+        }
+    }
+    let (start, stop) = match (bounds[0], bounds[1]) {
+        (Some(stop), None) => (0, stop),
+        (Some(start), Some(stop)) => (start, stop),
+        _ => unreachable!("start is a required parameter"),
     };
-    Value::String(str_value)
+    let step = bounds[2].unwrap_or(1);
+    if step == 0 {
+        return Err(vm.runtime_error(
+            RuntimeErrorKind::InvalidOperands,
+            "range step must not be zero",
+        ));
+    }
+    Ok(Value::Range(Range::new(start, stop, step)))
+}
+
+// whether the object is an instance of the class or of one of its subclasses
+fn isinstance_impl(vm: &mut VM) -> Result<Value, RuntimeError> {
+    let object = vm.get_function_argument_by_index(0);
+    let class = vm.get_function_argument_by_index(1);
+    if !matches!(class.get().value(), Value::Class(_)) {
+        let message = format!("expected a class, got {}", class.get().value().type_name());
+        return Err(vm.runtime_error(RuntimeErrorKind::InvalidOperands, message));
+    }
+    let result = match object.get().value() {
+        Value::Instance(instance) => instance
+            .class()
+            .is_some_and(|instance_class| is_subclass(&instance_class, &class)),
+        _ => false,
+    };
+    Ok(Value::Boolean(result))
 }
 
 #[derive(Default, Debug)]
@@ -40,16 +159,75 @@ impl RegisterableExtension for &BasicFunctions {
         vec![
             NativeFunctionInfoBuilder::default()
                 .address(println_impl)
-                .n_params(1)
+                .params(vec![
+                    NativeParam::rest("values"),
+                    NativeParam::with_default("ending", || Value::String("\n".to_string()))
+                        .keyword_only(),
+                ])
                 .name("println".to_string())
                 .build()
                 .unwrap(),
             NativeFunctionInfoBuilder::default()
                 .address(str_impl)
-                .n_params(1)
+                .params(vec![NativeParam::required("value")])
                 .name("str".to_string())
                 .build()
                 .unwrap(),
+            NativeFunctionInfoBuilder::default()
+                .address(len_impl)
+                .params(vec![NativeParam::required("value")])
+                .name("len".to_string())
+                .build()
+                .unwrap(),
+            NativeFunctionInfoBuilder::default()
+                .address(keys_impl)
+                .params(vec![NativeParam::required("dict")])
+                .name("keys".to_string())
+                .build()
+                .unwrap(),
+            NativeFunctionInfoBuilder::default()
+                .address(values_impl)
+                .params(vec![NativeParam::required("dict")])
+                .name("values".to_string())
+                .build()
+                .unwrap(),
+            NativeFunctionInfoBuilder::default()
+                .address(has_impl)
+                .params(vec![
+                    NativeParam::required("dict"),
+                    NativeParam::required("key"),
+                ])
+                .name("has".to_string())
+                .build()
+                .unwrap(),
+            NativeFunctionInfoBuilder::default()
+                .address(remove_impl)
+                .params(vec![
+                    NativeParam::required("dict"),
+                    NativeParam::required("key"),
+                ])
+                .name("remove".to_string())
+                .build()
+                .unwrap(),
+            NativeFunctionInfoBuilder::default()
+                .address(range_impl)
+                .params(vec![
+                    NativeParam::required("start"),
+                    NativeParam::with_default("stop", || Value::Null),
+                    NativeParam::with_default("step", || Value::Integer(1)),
+                ])
+                .name("range".to_string())
+                .build()
+                .unwrap(),
+            NativeFunctionInfoBuilder::default()
+                .address(isinstance_impl)
+                .params(vec![
+                    NativeParam::required("object"),
+                    NativeParam::required("class"),
+                ])
+                .name("isinstance".to_string())
+                .build()
+                .unwrap(),
         ]
     }
 }
@@ -1,12 +1,59 @@
 use derive_builder::Builder;
 
+use crate::object::{Signature, Value};
 use crate::vm::VM;
 pub mod basic;
 
+// a parameter of a native function. the default is built when the function is registered.
+// params are declared in order: positional ones, then at most one rest param, then
+// keyword-only ones.
+#[derive(Debug, Clone)]
+pub struct NativeParam {
+    name: String,
+    default: Option<fn() -> Value>,
+    keyword_only: bool,
+    rest: bool,
+}
+
+impl NativeParam {
+    pub fn required(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            default: None,
+            keyword_only: false,
+            rest: false,
+        }
+    }
+
+    // receives the extra positional arguments as a list, so the function takes
+    // any number of arguments above the required ones
+    pub fn rest(name: &str) -> Self {
+        Self {
+            rest: true,
+            ..Self::required(name)
+        }
+    }
+
+    pub fn with_default(name: &str, default: fn() -> Value) -> Self {
+        Self {
+            default: Some(default),
+            ..Self::required(name)
+        }
+    }
+
+    // the parameter can only be passed by keyword
+    pub fn keyword_only(self) -> Self {
+        Self {
+            keyword_only: true,
+            ..self
+        }
+    }
+}
+
 #[derive(Debug, Clone, Builder)]
 pub struct NativeFunctionInfo {
     address: crate::object::NativeFunction,
-    n_params: usize,
+    params: Vec<NativeParam>,
     name: String,
 }
 
@@ -15,13 +62,25 @@ impl NativeFunctionInfo {
         &self.address
     }
 
-    pub fn n_params(&self) -> usize {
-        self.n_params
+    pub fn params(&self) -> &[NativeParam] {
+        &self.params
     }
 
     pub fn name(&self) -> &String {
         &self.name
     }
+
+    pub fn signature(&self) -> Signature {
+        Signature::new(
+            self.params.iter().map(|p| p.name.clone()).collect(),
+            self.params
+                .iter()
+                .filter(|p| !p.keyword_only && !p.rest)
+                .count(),
+            self.params.iter().any(|p| p.rest),
+            self.params.iter().map(|p| p.default.is_some()).collect(),
+        )
+    }
 }
 
 pub trait RegisterableExtension {
@@ -31,10 +90,12 @@ pub trait RegisterableExtension {
 pub fn register_native(vm: &mut VM, extension: impl RegisterableExtension) {
     let functions = extension.register();
     for f in functions {
-        let f_info = crate::object::FunctionInfo::new(
-            crate::object::FunctionAddress::Native(f.address().clone()),
-            f.n_params(),
-        );
-        vm.register_native(f.name(), &f_info);
+        let defaults = f
+            .params()
+            .iter()
+            .filter_map(|p| p.default)
+            .map(|d| d())
+            .collect();
+        vm.register_native(f.name(), *f.address(), f.signature(), defaults);
     }
 }
@@ -1,4 +1,6 @@
 use factory::compiler::Compiler;
+use factory::diagnostics::Diagnostic;
+use factory::object::Value;
 use factory::parser::program as parse_program;
 use factory::vm::VM;
 use nom::Finish;
@@ -13,8 +15,8 @@ fn main() {
     // parse input
     let program = parse_program(LocatedSpan::new(&input)).finish();
     if let Err(e) = program {
-        println!("{:#?}", e);
-        return;
+        eprint!("{}", Diagnostic::from_parse_error(&e).render(&input));
+        std::process::exit(1);
     }
     let program = program.unwrap();
     println!("remaining: {:?}", program);
@@ -26,12 +28,23 @@ fn main() {
     }
 
     let mut compiler = Compiler::new();
-    compiler.compile_top(&program[0]);
-    let code = compiler.link();
+    if let Err(errors) = compiler.compile_top(&program) {
+        for e in errors.iter() {
+            eprint!("{}", Diagnostic::from_compile_error(e).render(&input));
+        }
+        std::process::exit(1);
+    }
+    let linked = match compiler.link() {
+        Ok(linked) => linked,
+        Err(e) => {
+            eprintln!("link error: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     // write code to code.txt
     let mut file = std::fs::File::create("code.txt").unwrap();
-    for (i, c) in code.iter().enumerate() {
+    for (i, c) in linked.code().iter().enumerate() {
         writeln!(file, "{}: {:#?}", i, c).unwrap();
     }
 
@@ -46,13 +59,13 @@ fn main() {
     });
 
     // start user code
-    vm.set_code(code);
-    loop {
-        vm.step_code();
-        // vm.dump_stack();
-        // if let Some(v) = vm.stack_top() {
-        //     println!("{:?}", v);
-        // }
-        // vm.gc_debug();
+    vm.set_program(linked);
+    match vm.run() {
+        Ok(Value::Integer(exit_code)) => std::process::exit(exit_code as i32),
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
     }
 }
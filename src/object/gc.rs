@@ -24,6 +24,10 @@ impl Object {
         Self::new_from_value(Value::const_int(value))
     }
 
+    pub fn const_float(value: f64) -> Self {
+        Self::new_from_value(Value::const_float(value))
+    }
+
     pub fn const_bool(value: bool) -> Self {
         Self::new_from_value(Value::const_bool(value))
     }
@@ -43,6 +47,10 @@ impl Object {
     pub fn value(&self) -> &Value {
         &self.value
     }
+
+    pub fn value_mut(&mut self) -> &mut Value {
+        &mut self.value
+    }
 }
 
 #[derive(Debug)]
@@ -79,6 +87,11 @@ impl ObjectPtr {
     pub fn is_null(&self) -> bool {
         self.object.is_null()
     }
+
+    // whether both pointers refer to the same object
+    pub fn ptr_eq(&self, other: &ObjectPtr) -> bool {
+        self.object == other.object
+    }
 }
 
 impl Clone for ObjectPtr {
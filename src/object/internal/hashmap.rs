@@ -2,107 +2,68 @@ use crate::object::{ObjectPtr, Value};
 
 pub const DEFAULT_HASHMAP_SIZE: usize = 16;
 
+// the table is rebuilt once more than 3/4 of its slots are in use
+// (occupied or deleted), so probing always finds an empty slot.
+// it is also rebuilt once entries holds as many items as there are slots,
+// so that entries removed and re-added through tombstones don't pile up.
+const MAX_LOAD_NUMERATOR: usize = 3;
+const MAX_LOAD_DENOMINATOR: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+enum Slot {
+    Empty,
+    // tombstone left by remove. probing continues past it.
+    Deleted,
+    // index into entries
+    Occupied(usize),
+}
+
 #[derive(Debug, Clone)]
 struct Entry {
     key: ObjectPtr,
     value: ObjectPtr,
-    occupied: bool,
-}
-
-impl Default for Entry {
-    fn default() -> Self {
-        Self {
-            key: ObjectPtr::null(),
-            value: ObjectPtr::null(),
-            occupied: false,
-        }
-    }
 }
 
 impl Entry {
     pub fn new(key: ObjectPtr, value: ObjectPtr) -> Self {
-        Self {
-            key,
-            value,
-            occupied: true,
-        }
+        Self { key, value }
     }
 
-    pub fn is_occupied(&self) -> bool {
-        self.occupied
-    }
-
-    pub fn is_null(&self) -> bool {
-        !self.occupied
-    }
-
-    pub fn key(&self) -> ObjectPtr {
-        self.key.clone()
-    }
-
-    pub fn value(&self) -> ObjectPtr {
-        self.value.clone()
-    }
-
-    pub fn replace(&mut self, key: ObjectPtr, value: ObjectPtr) {
-        self.key = key;
-        self.value = value;
-        self.occupied = true;
-    }
-
-    pub fn set_value(&mut self, value: ObjectPtr) {
-        self.value = value;
-    }
-
-    pub fn clear(&mut self) {
-        self.key = ObjectPtr::null();
-        self.value = ObjectPtr::null();
-        self.occupied = false;
-    }
-
-    pub fn key_equals(&self, key: &ObjectPtr) -> bool {
-        if self.is_null() {
-            false
-        } else {
-            objectptr_string_eq(&self.key, key)
-        }
+    pub fn key_equals(&self, key: &Value) -> bool {
+        self.key.get().value().equals(key)
     }
 
     pub fn pointers(&self) -> Vec<ObjectPtr> {
-        if self.is_null() {
-            vec![]
-        } else {
-            vec![self.key.clone(), self.value.clone()]
-        }
+        vec![self.key.clone(), self.value.clone()]
     }
 }
 
+// open-addressing hash table. entries are kept in insertion order,
+// and the slot table only stores indices into them.
 #[derive(Debug)]
 pub struct HashMap {
-    map_size: usize,
-    data: Vec<Entry>,
+    slots: Vec<Slot>,
+    entries: Vec<Option<Entry>>,
+    len: usize,
+    // occupied + deleted slots
+    used_slots: usize,
 }
 
-fn hash_string(s: &str) -> usize {
-    let mut hash = 5381;
-    for c in s.chars() {
-        hash = ((hash << 5) + hash) + (c as usize);
-    }
-    hash
-}
-
-fn objectptr_string_eq(p1: &ObjectPtr, p2: &ObjectPtr) -> bool {
-    match (p1.get().value(), p2.get().value()) {
-        (Value::String(s1), Value::String(s2)) => s1 == s2,
-        _ => false,
+// keys must be hashable. callers check this with Value::hash before inserting.
+fn hash_key(key: &Value) -> usize {
+    match key.hash() {
+        Some(hash) => hash as usize,
+        None => panic!("unhashable key"),
     }
 }
 
 impl HashMap {
     pub fn new(initial_size: usize) -> Self {
         Self {
-            map_size: initial_size,
-            data: vec![Entry::default(); initial_size],
+            slots: vec![Slot::Empty; initial_size.max(1)],
+            entries: Vec::new(),
+            len: 0,
+            used_slots: 0,
         }
     }
 
@@ -110,75 +71,234 @@ impl HashMap {
         Self::new(DEFAULT_HASHMAP_SIZE)
     }
 
-    pub fn put(&mut self, key: ObjectPtr, value: ObjectPtr) {
-        match key.get().value() {
-            Value::String(s) => {
-                let hash = hash_string(s);
-                let initial_index = hash % self.map_size;
-                let mut index = initial_index;
-
-                loop {
-                    if index == initial_index {
-                        panic!("hashmap is full");
-                    }
+    // returns the slot holding `key`, or otherwise the slot where it should be inserted
+    // (the first tombstone on the probe sequence, or the empty slot ending it)
+    fn probe(&self, key: &Value) -> Result<usize, usize> {
+        let map_size = self.slots.len();
+        let mut index = hash_key(key) % map_size;
+        let mut first_deleted = None;
 
-                    if self.data[index].is_null() {
-                        self.data[index].replace(key, value);
-                        break;
-                    } else {
-                        if self.data[index].key_equals(&key) {
-                            self.data[index].set_value(value);
-                            break;
-                        } else {
-                            index = (index + 1) % self.map_size;
-                            continue;
-                        }
+        loop {
+            match self.slots[index] {
+                Slot::Empty => return Err(first_deleted.unwrap_or(index)),
+                Slot::Deleted => {
+                    if first_deleted.is_none() {
+                        first_deleted = Some(index);
+                    }
+                }
+                Slot::Occupied(i) => {
+                    if self.entries[i].as_ref().unwrap().key_equals(key) {
+                        return Ok(index);
                     }
                 }
             }
-            _ => {
-                panic!("key must be string");
-            }
+            index = (index + 1) % map_size;
         }
     }
 
-    fn find_index(&self, key: ObjectPtr) -> Option<usize> {
-        match key.get().value() {
-            Value::String(s) => {
-                let hash = hash_string(s);
-                let initial_index = hash % self.map_size;
-                let mut index = initial_index;
+    fn needs_resize(&self) -> bool {
+        (self.used_slots + 1) * MAX_LOAD_DENOMINATOR > self.slots.len() * MAX_LOAD_NUMERATOR
+            || self.entries.len() >= self.slots.len()
+    }
 
-                loop {
-                    if index == initial_index {
-                        panic!("hashmap is full");
-                    }
+    // rebuilds the table, dropping tombstones and growing it if it is mostly live entries
+    fn resize(&mut self) {
+        let mut new_size = self.slots.len();
+        while (self.len + 1) * 2 * MAX_LOAD_DENOMINATOR > new_size * MAX_LOAD_NUMERATOR {
+            new_size *= 2;
+        }
 
-                    if self.data[index].is_null() {
-                        return None;
-                    } else {
-                        if self.data[index].key_equals(&key) {
-                            return Some(index);
-                        } else {
-                            index = (index + 1) % self.map_size;
-                            continue;
-                        }
-                    }
+        let entries = std::mem::take(&mut self.entries);
+        self.slots = vec![Slot::Empty; new_size];
+        self.len = 0;
+        self.used_slots = 0;
+        for entry in entries.into_iter().flatten() {
+            self.put(entry.key, entry.value);
+        }
+    }
+
+    pub fn put(&mut self, key: ObjectPtr, value: ObjectPtr) {
+        match self.probe(key.get().value()) {
+            Ok(slot) => {
+                if let Slot::Occupied(i) = self.slots[slot] {
+                    self.entries[i].as_mut().unwrap().value = value;
+                }
+            }
+            Err(_) if self.needs_resize() => {
+                self.resize();
+                self.put(key, value);
+            }
+            Err(slot) => {
+                if let Slot::Empty = self.slots[slot] {
+                    self.used_slots += 1;
                 }
+                self.slots[slot] = Slot::Occupied(self.entries.len());
+                self.entries.push(Some(Entry::new(key, value)));
+                self.len += 1;
             }
-            _ => panic!("key must be string"),
+        }
+    }
+
+    fn find_index(&self, key: &Value) -> Option<usize> {
+        match self.probe(key) {
+            Ok(slot) => match self.slots[slot] {
+                Slot::Occupied(i) => Some(i),
+                _ => None,
+            },
+            Err(_) => None,
         }
     }
 
     pub fn get(&self, key: ObjectPtr) -> Option<ObjectPtr> {
-        self.find_index(key).map(|i| self.data[i].value().clone())
+        self.lookup(key.get().value())
+    }
+
+    // same as get, but takes the key by value so that no key object has to be allocated
+    pub fn lookup(&self, key: &Value) -> Option<ObjectPtr> {
+        self.find_index(key)
+            .map(|i| self.entries[i].as_ref().unwrap().value.clone())
     }
 
     pub fn exists(&self, key: ObjectPtr) -> bool {
-        self.find_index(key).is_some()
+        self.find_index(key.get().value()).is_some()
+    }
+
+    // removes `key` and returns its value. the slot is left as a tombstone
+    // so that keys probed past it can still be found.
+    pub fn remove(&mut self, key: ObjectPtr) -> Option<ObjectPtr> {
+        let slot = self.probe(key.get().value()).ok()?;
+        let Slot::Occupied(i) = self.slots[slot] else {
+            return None;
+        };
+        self.slots[slot] = Slot::Deleted;
+        self.len -= 1;
+        self.entries[i].take().map(|e| e.value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // (key, value) pairs in insertion order
+    pub fn entries(&self) -> Vec<(ObjectPtr, ObjectPtr)> {
+        self.entries
+            .iter()
+            .flatten()
+            .map(|e| (e.key.clone(), e.value.clone()))
+            .collect()
     }
 
     pub fn pointer(&self) -> Vec<ObjectPtr> {
-        self.data.iter().map(|e| e.pointers()).flatten().collect()
+        self.entries
+            .iter()
+            .flatten()
+            .flat_map(|e| e.pointers())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Object;
+
+    // the objects are leaked, which is fine for a test
+    fn int(i: i64) -> ObjectPtr {
+        ObjectPtr::wrap(Object::const_int(i))
+    }
+
+    fn keys(map: &HashMap) -> Vec<i64> {
+        map.entries()
+            .iter()
+            .map(|(k, _)| match k.get().value() {
+                Value::Integer(i) => *i,
+                v => panic!("unexpected key {:?}", v),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn insertion_order_survives_growth() {
+        let mut map = HashMap::new(4);
+        let order: Vec<i64> = (0..100).map(|i| (i * 37) % 101).collect();
+        for &i in &order {
+            map.put(int(i), int(i * 2));
+        }
+        assert!(map.slots.len() > 4);
+        assert_eq!(keys(&map), order);
+        for &i in &order {
+            assert!(matches!(
+                map.lookup(&Value::Integer(i)).unwrap().get().value(),
+                Value::Integer(v) if *v == i * 2
+            ));
+        }
+    }
+
+    #[test]
+    fn reinserting_removed_keys_does_not_grow_entries() {
+        let mut map = HashMap::new_default();
+        for i in 0..5 {
+            map.put(int(i), int(i));
+        }
+        for _ in 0..10_000 {
+            assert!(map.remove(int(1)).is_some());
+            map.put(int(1), int(7));
+        }
+        assert!(map.entries.len() <= map.slots.len());
+        assert_eq!(map.slots.len(), DEFAULT_HASHMAP_SIZE);
+        assert_eq!(keys(&map), vec![0, 2, 3, 4, 1]);
+    }
+
+    #[test]
+    fn lookup_probes_past_a_tombstone() {
+        let mut map = HashMap::new_default();
+        let size = map.slots.len();
+        let home = |i: i64| hash_key(&Value::Integer(i)) % size;
+        let first = 0;
+        let second = (1..).find(|&i| home(i) == home(first)).unwrap();
+
+        map.put(int(first), int(1));
+        map.put(int(second), int(2));
+        map.remove(int(first));
+        assert!(matches!(map.slots[home(first)], Slot::Deleted));
+        assert!(map.lookup(&Value::Integer(first)).is_none());
+        assert!(matches!(
+            map.lookup(&Value::Integer(second)).unwrap().get().value(),
+            Value::Integer(2)
+        ));
+
+        // the tombstone is reused by the next key inserted on the chain
+        map.put(int(first), int(3));
+        assert!(matches!(map.slots[home(first)], Slot::Occupied(_)));
+        assert_eq!(keys(&map), vec![second, first]);
+    }
+
+    #[test]
+    fn len_counts_live_entries() {
+        let mut map = HashMap::new_default();
+        assert!(map.is_empty());
+        for i in 0..10 {
+            map.put(int(i), int(i));
+        }
+        map.put(int(3), int(30));
+        assert_eq!(map.len(), 10);
+        for i in 0..10 {
+            if i % 2 == 0 {
+                assert!(map.remove(int(i)).is_some());
+            }
+        }
+        assert!(map.remove(int(0)).is_none());
+        assert_eq!(map.len(), 5);
+        assert!(!map.is_empty());
+        for i in (1..10).step_by(2) {
+            map.remove(int(i));
+        }
+        assert_eq!(map.len(), 0);
+        assert!(map.is_empty());
+        assert!(map.entries().is_empty());
     }
 }
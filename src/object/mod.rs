@@ -1,17 +1,21 @@
 mod gc;
-mod internal;
+pub(crate) mod internal;
 mod value;
 
 // runtime object used in Factory interpreter
 pub use gc::GCSystem;
 pub use gc::Object;
 pub use gc::ObjectPtr;
+pub use value::is_subclass;
+pub use value::BoundMethod;
+pub use value::BuiltinIterator;
+pub use value::ClassObject;
+pub use value::Closure;
 pub use value::FunctionAddress;
 pub use value::FunctionInfo;
+pub use value::Instance;
 pub use value::NativeFunction;
+pub use value::Range;
+pub use value::Signature;
+pub use value::Upvalue;
 pub use value::Value;
-
-#[derive(Debug, Clone)]
-pub struct ClassObject {}
-
-pub struct DictObject {}
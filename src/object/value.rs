@@ -1,18 +1,40 @@
-use crate::vm::VM;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+use crate::vm::{RuntimeError, VM};
 
 use super::internal::hashmap::HashMap as MyHashMap;
 use super::ObjectPtr;
 
+// the int a float is equal to, if any
+fn integral_float(f: f64) -> Option<i64> {
+    if f.fract() == 0.0 && (i64::MIN as f64..i64::MAX as f64).contains(&f) {
+        Some(f as i64)
+    } else {
+        None
+    }
+}
+
 #[derive(Debug)]
 pub enum Value {
     Invalid,
     Null,
     Integer(i64),
+    Float(f64),
     Boolean(bool),
     Function(Box<FunctionInfo>),
+    Closure(Closure),
+    Upvalue(Upvalue),
     String(String),
+    List(Vec<ObjectPtr>),
+    Tuple(Vec<ObjectPtr>),
+    Dict(MyHashMap),
+    Class(Box<ClassObject>),
     Instance(Instance),
-    // Dict()
+    BoundMethod(BoundMethod),
+    Range(Range),
+    Iterator(BuiltinIterator),
 }
 
 impl Value {
@@ -20,6 +42,10 @@ impl Value {
         Value::Integer(value)
     }
 
+    pub fn const_float(value: f64) -> Self {
+        Value::Float(value)
+    }
+
     pub fn const_bool(value: bool) -> Self {
         Value::Boolean(value)
     }
@@ -28,15 +54,181 @@ impl Value {
         Value::String(value)
     }
 
+    // name of the value's type, used in error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Invalid => "uninitialized value",
+            Value::Null => "null",
+            Value::Integer(_) => "int",
+            Value::Float(_) => "float",
+            Value::Boolean(_) => "bool",
+            Value::Function(_) | Value::Closure(_) => "function",
+            Value::Upvalue(_) => "upvalue",
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Tuple(_) => "tuple",
+            Value::Dict(_) => "dict",
+            Value::Class(_) => "class",
+            Value::Instance(_) => "instance",
+            Value::BoundMethod(_) => "method",
+            Value::Range(_) => "range",
+            Value::Iterator(_) => "iterator",
+        }
+    }
+
+    // whether the value counts as true in a condition. false, null, zero and
+    // empty containers are false, everything else is true.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Boolean(b) => *b,
+            Value::Integer(i) => *i != 0,
+            Value::Float(f) => *f != 0.0,
+            Value::String(s) => !s.is_empty(),
+            Value::List(elements) | Value::Tuple(elements) => !elements.is_empty(),
+            Value::Dict(map) => !map.is_empty(),
+            Value::Range(range) => !range.is_empty(),
+            _ => true,
+        }
+    }
+
+    // numeric value as float, used for int/float promotion
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Integer(i) => Some(*i as f64),
+            Value::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    // hash of an immutable value, or None if the value cannot be used as a dict key.
+    // ints and integral floats hash alike because they compare equal.
+    pub fn hash(&self) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        self.hash_into(&mut hasher)?;
+        Some(hasher.finish())
+    }
+
+    fn hash_into(&self, state: &mut DefaultHasher) -> Option<()> {
+        match self {
+            Value::Null => 0u8.hash(state),
+            Value::Boolean(b) => {
+                1u8.hash(state);
+                b.hash(state);
+            }
+            Value::Integer(i) => {
+                2u8.hash(state);
+                i.hash(state);
+            }
+            Value::Float(f) if integral_float(*f).is_some() => {
+                Value::Integer(*f as i64).hash_into(state)?
+            }
+            Value::Float(f) => {
+                3u8.hash(state);
+                f.to_bits().hash(state);
+            }
+            Value::String(s) => {
+                4u8.hash(state);
+                s.hash(state);
+            }
+            Value::Tuple(elements) => {
+                5u8.hash(state);
+                elements.len().hash(state);
+                for element in elements {
+                    element.get().value().hash_into(state)?;
+                }
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    // equality of hashable values, as used for dict keys
+    pub fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Null, Value::Null) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Tuple(a), Value::Tuple(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b)
+                        .all(|(x, y)| x.get().value().equals(y.get().value()))
+            }
+            (Value::Float(a), Value::Float(b)) => a == b,
+            // compared exactly: converting the int to a float could round it
+            (Value::Integer(i), Value::Float(f)) | (Value::Float(f), Value::Integer(i)) => {
+                integral_float(*f) == Some(*i)
+            }
+            _ => false,
+        }
+    }
+
+    // equality as tested by `==`. lists, tuples and dicts compare element-wise,
+    // and values without a notion of equality (functions, classes, instances, ...)
+    // are only equal to themselves.
+    pub fn eq_value(&self, other: &Value) -> bool {
+        self.eq_value_seen(other, &mut HashSet::new())
+    }
+
+    // `seen` holds the pairs of containers already compared or being compared.
+    // they are taken as equal when met again, so that containers holding
+    // themselves don't recurse forever. if such a pair turns out to differ,
+    // the comparison that started it fails too, so the result is still right.
+    fn eq_value_seen(
+        &self,
+        other: &Value,
+        seen: &mut HashSet<(*const Value, *const Value)>,
+    ) -> bool {
+        let is_container = matches!(
+            (self, other),
+            (Value::List(_), Value::List(_))
+                | (Value::Tuple(_), Value::Tuple(_))
+                | (Value::Dict(_), Value::Dict(_))
+        );
+        if is_container && (std::ptr::eq(self, other) || !seen.insert((self, other))) {
+            return true;
+        }
+        match (self, other) {
+            (Value::List(a), Value::List(b)) | (Value::Tuple(a), Value::Tuple(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b)
+                        .all(|(x, y)| x.get().value().eq_value_seen(y.get().value(), seen))
+            }
+            (Value::Dict(a), Value::Dict(b)) => {
+                a.len() == b.len()
+                    && a.entries().iter().all(|(key, value)| {
+                        b.lookup(key.get().value()).is_some_and(|v| {
+                            value.get().value().eq_value_seen(v.get().value(), seen)
+                        })
+                    })
+            }
+            _ if self.hash().is_some() && other.hash().is_some() => self.equals(other),
+            _ => std::ptr::eq(self, other),
+        }
+    }
+
     pub fn children(&self) -> Vec<ObjectPtr> {
         match self {
             Value::Invalid => vec![],
             Value::Null => vec![],
             Value::Integer(_) => vec![],
+            Value::Float(_) => vec![],
             Value::Boolean(_) => vec![],
-            Value::Function(_) => vec![],
+            Value::Function(f) => f.children(),
+            Value::Closure(c) => c.children(),
+            Value::Upvalue(u) => u.children(),
             Value::String(_) => vec![],
+            Value::List(elements) => elements.clone(),
+            Value::Tuple(elements) => elements.clone(),
+            Value::Dict(map) => map.pointer(),
+            Value::Class(c) => c.children(),
             Value::Instance(i) => i.children(),
+            Value::BoundMethod(m) => m.children(),
+            Value::Range(_) => vec![],
+            Value::Iterator(i) => i.children(),
         }
     }
 
@@ -45,35 +237,201 @@ impl Value {
             Value::Invalid => vec![],
             Value::Null => vec![],
             Value::Integer(_) => vec![],
+            Value::Float(_) => vec![],
             Value::Boolean(_) => vec![],
-            Value::Function(_) => vec![],
+            Value::Function(f) => f.children(),
+            Value::Closure(c) => c.children(),
+            Value::Upvalue(u) => u.children(),
             Value::String(_) => vec![],
+            Value::List(elements) => elements.clone(),
+            Value::Tuple(elements) => elements.clone(),
+            Value::Dict(map) => map.pointer(),
+            Value::Class(c) => c.children(),
             Value::Instance(i) => i.children(),
+            Value::BoundMethod(m) => m.children(),
+            Value::Range(_) => vec![],
+            Value::Iterator(i) => i.children(),
         }
     }
 }
 
+// parameter names of a function and how arguments are bound to them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Signature {
+    params: Vec<String>,
+    // params before this index can also be passed by position, the rest only by keyword
+    n_positional: usize,
+    // whether the param at n_positional collects the extra positional arguments
+    rest: bool,
+    has_default: Vec<bool>,
+}
+
+impl Signature {
+    pub fn new(
+        params: Vec<String>,
+        n_positional: usize,
+        rest: bool,
+        has_default: Vec<bool>,
+    ) -> Self {
+        Self {
+            params,
+            n_positional,
+            rest,
+            has_default,
+        }
+    }
+
+    // required parameters that can all be passed by position
+    pub fn positional(params: Vec<String>) -> Self {
+        let n_params = params.len();
+        Self::new(params, n_params, false, vec![false; n_params])
+    }
+
+    pub fn params(&self) -> &[String] {
+        &self.params
+    }
+
+    pub fn n_positional(&self) -> usize {
+        self.n_positional
+    }
+
+    // index of the param collecting the extra positional arguments
+    pub fn rest_index(&self) -> Option<usize> {
+        self.rest.then_some(self.n_positional)
+    }
+
+    // fewest positional arguments a call must pass, if keyword-only params are passed by keyword
+    pub fn min_arity(&self) -> usize {
+        (0..self.n_positional)
+            .filter(|i| !self.has_default[*i])
+            .count()
+    }
+
+    // most positional arguments a call can pass, or None if there is no limit
+    pub fn max_arity(&self) -> Option<usize> {
+        match self.rest {
+            true => None,
+            false => Some(self.n_positional),
+        }
+    }
+
+    pub fn has_default(&self, index: usize) -> bool {
+        self.has_default[index]
+    }
+
+    pub fn n_defaults(&self) -> usize {
+        self.has_default.iter().filter(|d| **d).count()
+    }
+
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.params.iter().position(|p| p == name)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FunctionInfo {
     address: FunctionAddress,
-    n_params: usize,
+    signature: Signature,
+    // values of the parameters that have a default, in parameter order
+    defaults: Vec<ObjectPtr>,
+    // class the function was defined in, if it is a method. used to resolve `super`.
+    owner: Option<ObjectPtr>,
 }
 
 impl FunctionInfo {
-    pub fn new(address: FunctionAddress, n_params: usize) -> Self {
-        Self { address, n_params }
+    pub fn new(address: FunctionAddress, signature: Signature, defaults: Vec<ObjectPtr>) -> Self {
+        Self {
+            address,
+            signature,
+            defaults,
+            owner: None,
+        }
+    }
+
+    pub fn owner(&self) -> Option<ObjectPtr> {
+        self.owner.clone()
+    }
+
+    pub fn set_owner(&mut self, owner: ObjectPtr) {
+        self.owner = Some(owner);
+    }
+
+    pub fn children(&self) -> Vec<ObjectPtr> {
+        let mut pointers = self.defaults.clone();
+        pointers.extend(self.owner.clone());
+        pointers
     }
 
     pub fn address(&self) -> &FunctionAddress {
         &self.address
     }
 
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
     pub fn n_params(&self) -> usize {
-        self.n_params
+        self.signature.params().len()
     }
+
+    // default value of parameter `index`, if it has one
+    pub fn default_value(&self, index: usize) -> Option<ObjectPtr> {
+        if !self.signature.has_default(index) {
+            return None;
+        }
+        let n_before = (0..index)
+            .filter(|i| self.signature.has_default(*i))
+            .count();
+        Some(self.defaults[n_before].clone())
+    }
+}
+
+// a function together with the variables it captured from enclosing functions
+#[derive(Debug)]
+pub struct Closure {
+    function: ObjectPtr,
+    upvalues: Vec<ObjectPtr>,
 }
 
-pub type NativeFunction = fn(&mut VM) -> Value;
+impl Closure {
+    pub fn new(function: ObjectPtr, upvalues: Vec<ObjectPtr>) -> Self {
+        Self { function, upvalues }
+    }
+
+    pub fn function(&self) -> ObjectPtr {
+        self.function.clone()
+    }
+
+    pub fn upvalue(&self, index: usize) -> ObjectPtr {
+        self.upvalues[index].clone()
+    }
+
+    pub fn children(&self) -> Vec<ObjectPtr> {
+        let mut pointers = vec![self.function.clone()];
+        pointers.extend(self.upvalues.iter().cloned());
+        pointers
+    }
+}
+
+// a captured variable. it refers to a local slot while the function that owns
+// the slot is running, and holds the value itself once that function returns.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open { frame: usize, slot: usize },
+    Closed(ObjectPtr),
+}
+
+impl Upvalue {
+    pub fn children(&self) -> Vec<ObjectPtr> {
+        match self {
+            // the value of an open upvalue is kept alive by its frame
+            Upvalue::Open { .. } => vec![],
+            Upvalue::Closed(value) => vec![value.clone()],
+        }
+    }
+}
+
+pub type NativeFunction = fn(&mut VM) -> Result<Value, RuntimeError>;
 
 #[derive(Debug, Clone)]
 pub enum FunctionAddress {
@@ -107,9 +465,162 @@ impl Instance {
         self.fields.get(key.clone())
     }
 
+    pub fn class_name(&self) -> String {
+        match self.class.as_ref().map(|class| class.get().value()) {
+            Some(Value::Class(class)) => class.name().to_string(),
+            _ => "instance".to_string(),
+        }
+    }
+
+    pub fn field(&self, name: &str) -> Option<ObjectPtr> {
+        self.fields.lookup(&Value::String(name.to_string()))
+    }
+
     pub fn children(&self) -> Vec<ObjectPtr> {
         let mut pointers = self.class.clone().map_or_else(|| vec![], |c| vec![c]);
         pointers.extend(self.fields.pointer());
         pointers
     }
 }
+
+#[derive(Debug)]
+pub struct ClassObject {
+    name: String,
+    parent: Option<ObjectPtr>,
+    // method name -> function object
+    methods: MyHashMap,
+}
+
+impl ClassObject {
+    pub fn new(name: String, parent: Option<ObjectPtr>, methods: MyHashMap) -> Self {
+        Self {
+            name,
+            parent,
+            methods,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn parent(&self) -> Option<ObjectPtr> {
+        self.parent.clone()
+    }
+
+    // looks up a method on this class, then on its ancestors
+    pub fn method(&self, name: &str) -> Option<ObjectPtr> {
+        self.methods
+            .lookup(&Value::String(name.to_string()))
+            .or_else(|| match self.parent.as_ref()?.get().value() {
+                Value::Class(parent) => parent.method(name),
+                _ => None,
+            })
+    }
+
+    pub fn children(&self) -> Vec<ObjectPtr> {
+        let mut pointers = self.methods.pointer();
+        pointers.extend(self.parent.clone());
+        pointers
+    }
+}
+
+// integers from start up to (but excluding) stop, counting by step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    start: i64,
+    stop: i64,
+    step: i64,
+}
+
+impl Range {
+    // step must not be zero
+    pub fn new(start: i64, stop: i64, step: i64) -> Self {
+        Self { start, stop, step }
+    }
+
+    pub fn start(&self) -> i64 {
+        self.start
+    }
+
+    pub fn stop(&self) -> i64 {
+        self.stop
+    }
+
+    pub fn step(&self) -> i64 {
+        self.step
+    }
+
+    pub fn len(&self) -> usize {
+        let span = match self.step > 0 {
+            true => self.stop as i128 - self.start as i128,
+            false => self.start as i128 - self.stop as i128,
+        };
+        let step = (self.step as i128).abs();
+        ((span + step - 1) / step).max(0) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// progress of a `for` loop over a built-in value
+#[derive(Debug)]
+pub enum BuiltinIterator {
+    // a list or tuple. elements are read as the loop goes, so appended ones are visited too.
+    Sequence { sequence: ObjectPtr, index: usize },
+    // dict keys, fixed when the loop starts
+    Items { items: Vec<ObjectPtr>, index: usize },
+    // characters of a string
+    Chars { chars: Vec<char>, index: usize },
+    Range { next: i64, stop: i64, step: i64 },
+}
+
+impl BuiltinIterator {
+    pub fn children(&self) -> Vec<ObjectPtr> {
+        match self {
+            BuiltinIterator::Sequence { sequence, .. } => vec![sequence.clone()],
+            BuiltinIterator::Items { items, .. } => items.clone(),
+            BuiltinIterator::Chars { .. } | BuiltinIterator::Range { .. } => vec![],
+        }
+    }
+}
+
+// whether `class` is `ancestor` or inherits from it
+pub fn is_subclass(class: &ObjectPtr, ancestor: &ObjectPtr) -> bool {
+    if class.ptr_eq(ancestor) {
+        return true;
+    }
+    match class.get().value() {
+        Value::Class(c) => c
+            .parent()
+            .is_some_and(|parent| is_subclass(&parent, ancestor)),
+        _ => false,
+    }
+}
+
+// a method looked up through an instance. calling it passes the receiver as `self`.
+#[derive(Debug)]
+pub struct BoundMethod {
+    receiver: ObjectPtr,
+    function: ObjectPtr,
+}
+
+impl BoundMethod {
+    pub fn new(receiver: ObjectPtr, function: ObjectPtr) -> Self {
+        Self { receiver, function }
+    }
+
+    pub fn receiver(&self) -> ObjectPtr {
+        self.receiver.clone()
+    }
+
+    pub fn function(&self) -> ObjectPtr {
+        self.function.clone()
+    }
+
+    pub fn children(&self) -> Vec<ObjectPtr> {
+        vec![self.receiver.clone(), self.function.clone()]
+    }
+}
@@ -1,8 +1,12 @@
+use crate::object::Signature;
+
 #[derive(Debug, Clone)]
 pub enum Opcode {
     Nop,
     ConstNull,
+    ConstBool(bool),
     ConstInt(i64),
+    ConstFloat(f64),
     ConstString(String),
     Add2,
     Sub2,
@@ -16,17 +20,45 @@ pub enum Opcode {
     Le2,
     Gt2,
     Ge2,
+    Neg,
+    Not,
+    ToStr, // replaces the value on top of the stack with its display string
     Exit,
     Discard,
+    Dup,
     Store(usize),
     Load(usize),
+    LoadUpvalue(usize),
+    StoreUpvalue(usize),
     StoreGlobal(String),
     LoadGlobal(String),
     JmpIfTrue(usize),
     JmpAlways(usize),
     JmpIfFalse(usize),
-    CallNoKw(usize),              // count of arguments
-    CallKw(usize),                // count of arguments (excluding the last kwarg)
-    CreateFunction(usize, usize), // address, n_params
+    CallNoKw(usize),    // count of arguments
+    CallKw(usize),      // count of arguments (excluding the last kwarg)
+    CallSpread(bool),   // arguments as one list, followed by a kwarg dict if set
+    ConcatLists(usize), // count of lists or tuples joined into one list
+    BuildList(usize),   // count of elements
+    BuildTuple(usize),  // count of elements
+    BuildDict(usize),   // count of key-value pairs
+    GetIndex,
+    SetIndex,
+    CreateFunction(usize, Signature), // address, signature. pops the default values
+    MakeClosure(Vec<Capture>),        // wraps the function on top of the stack
+    CreateClass(String, usize),       // name, count of methods
+    GetAttr(String),
+    GetSuper(String),
+    SetAttr(String),
+    GetIter,        // replaces the value on top of the stack with an iterator over it
+    ForIter(usize), // address to jump to (popping the iterator) once it is exhausted
+    CloseUpvalues(Vec<usize>), // local slots whose upvalues are closed, as a loop iteration ends
     Return,
 }
+
+// where a closure takes each of its upvalues from when it is created
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    Local(usize),   // local slot of the enclosing function
+    Upvalue(usize), // upvalue of the enclosing function
+}
@@ -1,300 +1,845 @@
-use nom::bytes::complete::tag;
+use nom::bytes::complete::{is_not, tag, take_while, take_while_m_n};
 use nom::character::complete as cp;
-use nom::error::{context, ParseError};
-use nom::multi::{many0, separated_list0, separated_list1};
+use nom::error::{context, ContextError, ParseError};
+use nom::multi::{many0, many1, separated_list0, separated_list1};
 use nom::sequence as seq;
 use nom::{branch, combinator as comb, IResult};
 use nom_locate::LocatedSpan;
 
 use crate::ast::{
-    AssignmentStatement, BinaryExpression, BinaryOperator, ConditionalStatement, Expression,
-    FunCallExpression, FuncDefStatement, IndexExpression, LiteralExpression, NameExpression,
-    ObjectAssignmentStatement, ReturnStatement, Statement, WhileStatement,
+    Argument, AssignmentStatement, AttributeAssignmentStatement, AttributeExpression,
+    BinaryExpression, BinaryOperator, ClassDefStatement, ConditionalStatement, Expression,
+    ExpressionKind, ForStatement, FunCallExpression, FuncDefStatement, IndexExpression,
+    LambdaExpression, LiteralExpression, Location, NameExpression, ObjectAssignmentStatement,
+    Parameter, ReturnStatement, SourceSpan, Statement, StatementKind, StringPart, UnaryExpression,
+    UnaryOperator, WhileStatement,
 };
 
-type Span<'a> = LocatedSpan<&'a str>;
+pub type Span<'a> = LocatedSpan<&'a str>;
 
-type Result<'a, T> = IResult<Span<'a>, T, nom::error::VerboseError<Span<'a>>>;
+pub type Result<'a, T> = IResult<Span<'a>, T, nom::error::VerboseError<Span<'a>>>;
 
+fn location(input: Span) -> Location {
+    Location::new(
+        input.location_line(),
+        input.get_utf8_column(),
+        input.location_offset(),
+    )
+}
+
+fn source_span(start: Span, end: Span) -> SourceSpan {
+    SourceSpan::new(location(start), location(end))
+}
+
+// wraps a parser producing an ExpressionKind so that the resulting node
+// carries the span of the consumed input
+fn expression_node<'a, F>(mut parser: F) -> impl FnMut(Span<'a>) -> Result<'a, Expression>
+where
+    F: FnMut(Span<'a>) -> Result<'a, ExpressionKind>,
+{
+    move |input: Span<'a>| {
+        let (rest, kind) = parser(input)?;
+        Ok((rest, Expression::new(kind, source_span(input, rest))))
+    }
+}
+
+// same as expression_node, for statements
+fn statement_node<'a, F>(mut parser: F) -> impl FnMut(Span<'a>) -> Result<'a, Statement>
+where
+    F: FnMut(Span<'a>) -> Result<'a, StatementKind>,
+{
+    move |input: Span<'a>| {
+        let (rest, kind) = parser(input)?;
+        Ok((rest, Statement::new(kind, source_span(input, rest))))
+    }
+}
+
+fn binary_node(op: BinaryOperator, left: Expression, right: Expression) -> Expression {
+    let span = SourceSpan::merge(left.span(), right.span());
+    Expression::new(
+        ExpressionKind::Binary(BinaryExpression::new(op, left, right)),
+        span,
+    )
+}
+
+// `# ...` up to the end of the line
+fn line_comment(input: Span) -> Result<Span> {
+    comb::recognize(seq::pair(
+        tag("#"),
+        comb::opt(nom::bytes::complete::is_not("\r\n")),
+    ))(input)
+}
+
+// `#[` only opens a block comment when followed by whitespace or the end of
+// input, so that a line comment such as `#[note] ...` stays a line comment
+fn block_comment_open(input: Span) -> Result<Span> {
+    seq::terminated(
+        tag("#["),
+        comb::peek(branch::alt((cp::multispace1, comb::eof))),
+    )(input)
+}
+
+// `#[ ... ]#`, which may nest and span multiple lines
+fn block_comment(input: Span) -> Result<Span> {
+    fn body(input: Span) -> Result<()> {
+        let mut rest = input;
+        let mut depth = 1;
+        while depth > 0 {
+            if let Ok((r, _)) = block_comment_open(rest) {
+                depth += 1;
+                rest = r;
+            } else if let Ok((r, _)) = tag::<_, _, nom::error::VerboseError<Span>>("]#")(rest) {
+                depth -= 1;
+                rest = r;
+            } else {
+                // running out of input here means the comment is unterminated
+                let (r, _) = context("]#", cp::anychar)(rest)?;
+                rest = r;
+            }
+        }
+        Ok((rest, ()))
+    }
+
+    context(
+        "block_comment",
+        comb::recognize(seq::preceded(block_comment_open, comb::cut(body))),
+    )(input)
+}
+
+fn comment(input: Span) -> Result<Span> {
+    branch::alt((block_comment, line_comment))(input)
+}
+
+// whitespace helpers. comments count as whitespace everywhere in the grammar.
 fn white1(input: Span) -> Result<Span> {
-    comb::recognize(cp::multispace1)(input)
+    comb::recognize(many1(branch::alt((cp::multispace1, comment))))(input)
+}
+
+fn white0(input: Span) -> Result<Span> {
+    comb::recognize(many0(branch::alt((cp::multispace1, comment))))(input)
 }
 
 fn white_no_newline1(input: Span) -> Result<Span> {
-    comb::recognize(cp::space1)(input)
+    comb::recognize(many1(branch::alt((cp::space1, comment))))(input)
 }
 
 fn white_no_newline0(input: Span) -> Result<Span> {
-    comb::recognize(cp::space0)(input)
+    comb::recognize(many0(branch::alt((cp::space1, comment))))(input)
+}
+
+const KEYWORDS: &[&str] = &[
+    "if", "elif", "else", "end", "do", "while", "for", "in", "break", "continue", "return", "def",
+    "class", "super", "fn", "and", "or", "not", "true", "false", "null",
+];
+
+pub(crate) fn is_keyword(input: &str) -> bool {
+    KEYWORDS.contains(&input)
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// a word made of identifier characters, keyword or not
+fn word(input: Span) -> Result<Span> {
+    comb::recognize(seq::pair(
+        cp::satisfy(is_ident_start),
+        take_while(is_ident_char),
+    ))(input)
 }
 
-fn is_keyword(input: Span) -> bool {
-    let keywords = vec![
-        "if", "else", "end", "do", "while", "for", "in", "break", "continue", "return", "def",
-    ];
-    keywords.contains(&input)
+// matches the keyword `kw` only when it is not followed by another identifier character,
+// so that e.g. `double` is not read as `do` followed by `uble`
+fn keyword<'a>(kw: &'static str) -> impl FnMut(Span<'a>) -> Result<'a, Span<'a>> {
+    context(
+        kw,
+        seq::terminated(tag(kw), comb::not(cp::satisfy(is_ident_char))),
+    )
 }
 
+// matches a punctuation token. the token itself is recorded as context
+// so that diagnostics can report what was expected.
+fn expect<'a>(token: &'static str) -> impl FnMut(Span<'a>) -> Result<'a, Span<'a>> {
+    context(token, tag(token))
+}
+
+// `[A-Za-z_][A-Za-z0-9_]*`, except for keywords
 pub fn ident(input: Span) -> Result<Span> {
-    let (new_input, o) = context("ident", cp::alphanumeric1)(input)?;
-    if is_keyword(o) {
-        return Err(nom::Err::Error(nom::error::VerboseError::from_error_kind(
+    let (new_input, o) = context("ident", word)(input)?;
+    if is_keyword(&o) {
+        return Err(nom::Err::Error(nom::error::VerboseError::add_context(
             o,
-            nom::error::ErrorKind::Tag,
+            "ident",
+            nom::error::VerboseError::from_error_kind(o, nom::error::ErrorKind::Tag),
         )));
     }
     Ok((new_input, o))
 }
 
+// `kw = value` at the start of a statement, which can only be a mistaken
+// assignment to a keyword. fails hard so that the error names the keyword.
+fn keyword_assignment(input: Span) -> Result<Statement> {
+    let (_, (name, _)) = seq::pair(
+        comb::verify(word, |w: &Span| is_keyword(w)),
+        seq::tuple((white_no_newline0, tag("="), comb::not(tag("=")))),
+    )(input)?;
+    Err(nom::Err::Failure(nom::error::VerboseError::add_context(
+        name,
+        "keyword_as_name",
+        nom::error::VerboseError::from_error_kind(name, nom::error::ErrorKind::Verify),
+    )))
+}
+
+// digits that must fit in an i64. a literal that doesn't is reported
+// as such instead of being tried as anything else.
+fn int_literal(input: Span) -> Result<ExpressionKind> {
+    let (rest, digits) = cp::digit1(input)?;
+    match digits.parse::<i64>() {
+        Ok(i) => Ok((rest, ExpressionKind::Literal(LiteralExpression::Integer(i)))),
+        Err(_) => Err(nom::Err::Failure(nom::error::VerboseError::add_context(
+            digits,
+            "integer_too_large",
+            nom::error::VerboseError::from_error_kind(digits, nom::error::ErrorKind::Verify),
+        ))),
+    }
+}
+
 fn list_literal(input: Span) -> Result<Expression> {
     context(
         "list_literal",
-        comb::map(
-            seq::tuple((
+        expression_node(comb::map(
+            seq::preceded(
                 tag("["),
-                cp::multispace0,
-                // TODO: This should be a list of expressions
-                tag("]"),
-            )),
-            |(_, _, _)| Expression::Literal(LiteralExpression::List),
-        ),
+                comb::cut(seq::tuple((
+                    white0,
+                    separated_list0(seq::tuple((white0, tag(","), white0)), expression),
+                    white0,
+                    // allow a trailing comma
+                    comb::opt(seq::pair(tag(","), white0)),
+                    expect("]"),
+                ))),
+            ),
+            |(_, elements, _, _, _)| ExpressionKind::Literal(LiteralExpression::List(elements)),
+        )),
     )(input)
 }
 
+fn dict_literal(input: Span) -> Result<Expression> {
+    let pair = seq::tuple((expression, white0, expect(":"), white0, expression));
+    context(
+        "dict_literal",
+        expression_node(comb::map(
+            seq::preceded(
+                tag("{"),
+                comb::cut(seq::tuple((
+                    white0,
+                    separated_list0(seq::tuple((white0, tag(","), white0)), pair),
+                    white0,
+                    // allow a trailing comma
+                    comb::opt(seq::pair(tag(","), white0)),
+                    expect("}"),
+                ))),
+            ),
+            |(_, pairs, _, _, _)| {
+                ExpressionKind::Literal(LiteralExpression::Dict(
+                    pairs
+                        .into_iter()
+                        .map(|(key, _, _, _, value)| (key, value))
+                        .collect(),
+                ))
+            },
+        )),
+    )(input)
+}
+
+fn float_exponent(input: Span) -> Result<Span> {
+    comb::recognize(seq::tuple((
+        cp::one_of("eE"),
+        comb::opt(cp::one_of("+-")),
+        cp::digit1,
+    )))(input)
+}
+
 pub fn literal_expression(input: Span) -> Result<Expression> {
-    let int_lit = comb::map(cp::digit1, |s: Span| {
-        Expression::Literal(LiteralExpression::Integer(s.parse::<i64>().unwrap()))
-    });
-    let name = comb::map(ident, |s| {
-        Expression::Name(NameExpression::new(s.to_string()))
-    });
-    let string_lit = comb::map(
-        seq::tuple((tag("\""), nom::bytes::complete::is_not("\""), tag("\""))),
-        |(_, s, _)| Expression::Literal(LiteralExpression::String((&s as &str).to_string())),
-    );
+    // digits with a fraction and/or an exponent, e.g. `1.5`, `2e10`, `6.02e-23`
+    let float_lit = expression_node(comb::map(
+        comb::recognize(seq::tuple((
+            cp::digit1,
+            branch::alt((
+                comb::recognize(seq::tuple((
+                    seq::pair(cp::char('.'), cp::digit1),
+                    comb::opt(float_exponent),
+                ))),
+                float_exponent,
+            )),
+        ))),
+        |s: Span| ExpressionKind::Literal(LiteralExpression::Float(s.parse::<f64>().unwrap())),
+    ));
+    let int_lit = expression_node(int_literal);
+    let name = expression_node(comb::map(ident, |s| {
+        ExpressionKind::Name(NameExpression::new(s.to_string()))
+    }));
+    let constant = expression_node(branch::alt((
+        comb::map(keyword("true"), |_| {
+            ExpressionKind::Literal(LiteralExpression::Boolean(true))
+        }),
+        comb::map(keyword("false"), |_| {
+            ExpressionKind::Literal(LiteralExpression::Boolean(false))
+        }),
+        comb::map(keyword("null"), |_| {
+            ExpressionKind::Literal(LiteralExpression::Null)
+        }),
+    )));
     context(
         "literal_expression",
         branch::alt((
             list_literal,
-            context("int literal", int_lit),
+            dict_literal,
+            // a number running into a name, e.g. `3abc`, is neither
+            context(
+                "float literal",
+                seq::terminated(float_lit, comb::not(cp::satisfy(is_ident_char))),
+            ),
+            context(
+                "int literal",
+                seq::terminated(int_lit, comb::not(cp::satisfy(is_ident_char))),
+            ),
+            context("constant literal", constant),
             context("name literal", name),
-            context("string literal", string_lit),
+            context("string literal", string_literal),
         )),
     )(input)
 }
 
-fn elementary_expression(input: Span) -> Result<Expression> {
-    let paren_expr = seq::delimited(tag("("), expression, tag(")"));
-    context(
-        "elementary_expression",
-        branch::alt((literal_expression, context("paren expr", paren_expr))),
+// `\n`, `\t`, `\r`, `\0`, `\"`, `\\`, `\{`, `\}` or `\u{hex}`
+fn escape_sequence(input: Span) -> Result<char> {
+    let unicode = comb::map_opt(
+        seq::delimited(
+            tag("u{"),
+            take_while_m_n(1, 6, |c: char| c.is_ascii_hexdigit()),
+            tag("}"),
+        ),
+        |hex: Span| u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32),
+    );
+    seq::preceded(
+        cp::char('\\'),
+        comb::cut(context(
+            "escape_sequence",
+            branch::alt((
+                comb::value('\n', cp::char('n')),
+                comb::value('\t', cp::char('t')),
+                comb::value('\r', cp::char('r')),
+                comb::value('\0', cp::char('0')),
+                comb::value('"', cp::char('"')),
+                comb::value('\\', cp::char('\\')),
+                comb::value('{', cp::char('{')),
+                comb::value('}', cp::char('}')),
+                unicode,
+            )),
+        )),
     )(input)
 }
 
-fn indexing_expression(input: Span) -> Result<IndexExpression> {
-    let index_paren = context(
-        "index_paren",
-        comb::map(
-            seq::tuple((
-                cp::multispace0,
-                tag("["),
-                cp::space0,
+// `{expr}` inside a string literal
+fn interpolation(input: Span) -> Result<Expression> {
+    context(
+        "interpolation",
+        seq::preceded(
+            tag("{"),
+            comb::cut(seq::delimited(
+                white0,
                 expression,
-                cp::space0,
-                tag("]"),
+                seq::pair(white0, expect("}")),
             )),
-            |(_, _, _, arg, _, _)| arg,
         ),
-    );
-
-    let indexing = comb::map(
-        seq::tuple((elementary_expression, index_paren)),
-        |(callee, arg)| IndexExpression::new(callee, arg),
-    );
+    )(input)
+}
 
-    context("indexing", indexing)(input)
+// contents of a string literal up to its closing quote. a single-quoted string
+// ends with its line, while a triple-quoted one may span lines and contain `"`.
+fn string_contents(input: Span, triple: bool) -> Result<Vec<StringPart>> {
+    let special = if triple { "\"\\{" } else { "\"\\{\n" };
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut rest = input;
+    loop {
+        let plain: Result<Span> = is_not(special)(rest);
+        if let Ok((r, s)) = plain {
+            text.push_str(&s);
+            rest = r;
+        } else if rest.starts_with('\\') {
+            let (r, c) = escape_sequence(rest)?;
+            text.push(c);
+            rest = r;
+        } else if rest.starts_with('{') {
+            let (r, expr) = interpolation(rest)?;
+            if !text.is_empty() {
+                parts.push(StringPart::Text(std::mem::take(&mut text)));
+            }
+            parts.push(StringPart::Interpolation(expr));
+            rest = r;
+        } else if triple && rest.starts_with('"') && !rest.starts_with("\"\"\"") {
+            let (r, _) = tag("\"")(rest)?;
+            text.push('"');
+            rest = r;
+        } else {
+            break;
+        }
+    }
+    if !text.is_empty() || parts.is_empty() {
+        parts.push(StringPart::Text(text));
+    }
+    Ok((rest, parts))
 }
 
-pub fn call_expression(input: Span) -> Result<Expression> {
-    let call_paren = context(
-        "call_paren",
-        comb::map(
-            seq::tuple((cp::multispace0, tag("("), arg_list0, tag(")"))),
-            |(_, _, args, _)| args,
-        ),
+// `"..."` or `"""..."""`. a string without interpolations is a plain literal.
+fn string_literal(input: Span) -> Result<Expression> {
+    let triple = seq::preceded(
+        tag("\"\"\""),
+        comb::cut(seq::terminated(
+            |i| string_contents(i, true),
+            expect("\"\"\""),
+        )),
     );
-
-    let call = comb::map(
-        seq::tuple((elementary_expression, call_paren)),
-        |(callee, args)| Expression::FunCall(FunCallExpression::new(callee, args)),
+    let single = seq::preceded(
+        tag("\""),
+        comb::cut(seq::terminated(|i| string_contents(i, false), expect("\""))),
     );
-
     context(
-        "call_expression",
-        branch::alt((
-            context("call", call),
-            comb::map(indexing_expression, |e| Expression::Index(e)),
-            elementary_expression,
+        "string_literal",
+        expression_node(comb::map(
+            branch::alt((triple, single)),
+            |mut parts| match parts.as_slice() {
+                [StringPart::Text(_)] => match parts.remove(0) {
+                    StringPart::Text(s) => ExpressionKind::Literal(LiteralExpression::String(s)),
+                    StringPart::Interpolation(_) => unreachable!(),
+                },
+                _ => ExpressionKind::InterpolatedString(parts),
+            },
         )),
     )(input)
 }
 
-pub fn product_operator(input: Span) -> Result<BinaryOperator> {
+fn elementary_expression(input: Span) -> Result<Expression> {
     context(
-        "product_operator",
+        "elementary_expression",
         branch::alt((
-            comb::map(tag("*"), |_| BinaryOperator::Times),
-            comb::map(tag("/"), |_| BinaryOperator::Divide),
-            comb::map(tag("%"), |_| BinaryOperator::Modulo),
+            literal_expression,
+            super_attribute,
+            lambda_expression,
+            context("paren expr", paren_expression),
         )),
     )(input)
 }
 
-pub fn product_expression(input: Span) -> Result<Expression> {
-    let p = seq::tuple((
-        call_expression,
-        many0(seq::tuple((
-            cp::space0,
-            product_operator,
-            cp::space0,
-            product_expression,
+// `fn(params) do ... end`
+fn lambda_expression(input: Span) -> Result<Expression> {
+    let tup = seq::preceded(
+        keyword("fn"),
+        comb::cut(seq::tuple((
+            white_no_newline0,
+            expect("("),
+            white0,
+            param_list,
+            white0,
+            expect(")"),
+            white1,
+            block_stmt,
         ))),
-    ));
+    );
     context(
-        "product_expression",
-        comb::map(p, |(first, rest)| {
-            rest.into_iter().fold(first, |acc, (_, op, _, expr)| {
-                Expression::Binary(BinaryExpression::new(op, acc, expr))
-            })
-        }),
+        "lambda_expr",
+        expression_node(comb::map(tup, |(_, _, _, params, _, _, _, body)| {
+            ExpressionKind::Lambda(LambdaExpression::new(params, body))
+        })),
     )(input)
 }
 
-pub fn add_operator(input: Span) -> Result<BinaryOperator> {
-    context(
-        "add_operator",
-        branch::alt((
-            comb::map(tag("+"), |_| BinaryOperator::Plus),
-            comb::map(tag("-"), |_| BinaryOperator::Minus),
-        )),
-    )(input)
+// `super.name`. `super` cannot be used on its own.
+fn super_attribute(input: Span) -> Result<Expression> {
+    expression_node(comb::map(
+        seq::preceded(
+            keyword("super"),
+            comb::cut(seq::preceded(
+                seq::pair(white_no_newline0, expect(".")),
+                ident,
+            )),
+        ),
+        |name| ExpressionKind::SuperAttribute(name.to_string()),
+    ))(input)
 }
 
-pub fn add_expression(input: Span) -> Result<Expression> {
-    let p = seq::tuple((
-        product_expression,
-        many0(seq::tuple((
-            cp::space0,
-            add_operator,
-            cp::space0,
-            add_expression,
+// `(e)` only groups an expression, while `()`, `(e,)` and `(a, b)` build tuples
+fn paren_expression(input: Span) -> Result<Expression> {
+    let (rest, (_, (_, mut elements, _, trailing_comma, _))) = seq::pair(
+        tag("("),
+        comb::cut(seq::tuple((
+            white0,
+            separated_list0(seq::tuple((white0, tag(","), white0)), expression),
+            white0,
+            comb::opt(seq::pair(tag(","), white0)),
+            expect(")"),
         ))),
-    ));
-    context(
-        "add_expression",
-        comb::map(p, |(first, rest)| {
-            rest.into_iter().fold(first, |acc, (_, op, _, expr)| {
-                Expression::Binary(BinaryExpression::new(op, acc, expr))
-            })
-        }),
-    )(input)
+    )(input)?;
+
+    if elements.len() == 1 && trailing_comma.is_none() {
+        return Ok((rest, elements.remove(0)));
+    }
+    let kind = ExpressionKind::Literal(LiteralExpression::Tuple(elements));
+    Ok((rest, Expression::new(kind, source_span(input, rest))))
 }
 
-pub fn cmp_operator(input: Span) -> Result<BinaryOperator> {
-    context(
-        "cmp_operator",
-        branch::alt((
-            comb::map(tag("=="), |_| BinaryOperator::Eq),
-            comb::map(tag("!="), |_| BinaryOperator::Neq),
-            comb::map(tag("<"), |_| BinaryOperator::Lt),
-            comb::map(tag("<="), |_| BinaryOperator::Le),
-            comb::map(tag(">"), |_| BinaryOperator::Gt),
-            comb::map(tag(">="), |_| BinaryOperator::Ge),
-        )),
-    )(input)
+// suffix applied to an expression: call arguments, an index or an attribute name
+enum Postfix {
+    Call(Vec<Argument>, Vec<(String, Expression)>),
+    Index(Expression),
+    Attribute(String),
 }
 
-pub fn cmp_expression(input: Span) -> Result<Expression> {
-    let p = seq::tuple((
-        add_expression,
-        many0(seq::tuple((
-            cp::space0,
-            cmp_operator,
-            cp::space0,
-            cmp_expression,
-        ))),
-    ));
-    context(
-        "cmp_expression",
-        comb::map(p, |(first, rest)| {
-            rest.into_iter().fold(first, |acc, (_, op, _, expr)| {
-                Expression::Binary(BinaryExpression::new(op, acc, expr))
-            })
-        }),
-    )(input)
+fn postfix(input: Span) -> Result<Postfix> {
+    let call_paren = context(
+        "call_paren",
+        comb::map(
+            seq::tuple((
+                white_no_newline0,
+                tag("("),
+                comb::cut(seq::terminated(arg_list0, expect(")"))),
+            )),
+            |(_, _, (args, kwargs))| Postfix::Call(args, kwargs),
+        ),
+    );
+
+    let index_paren = context(
+        "index_paren",
+        comb::map(
+            seq::tuple((
+                white_no_newline0,
+                tag("["),
+                comb::cut(seq::tuple((
+                    white_no_newline0,
+                    expression,
+                    white_no_newline0,
+                    expect("]"),
+                ))),
+            )),
+            |(_, _, (_, arg, _, _))| Postfix::Index(arg),
+        ),
+    );
+
+    let attribute = context(
+        "attribute",
+        comb::map(
+            seq::tuple((white_no_newline0, tag("."), comb::cut(ident))),
+            |(_, _, name)| Postfix::Attribute(name.to_string()),
+        ),
+    );
+
+    branch::alt((call_paren, index_paren, attribute))(input)
+}
+
+// an elementary expression followed by any number of calls, indexings and
+// attribute accesses, e.g. `f(x)[0].y(z)`
+pub fn call_expression(input: Span) -> Result<Expression> {
+    let (mut rest, mut expr) = context("call_expression", elementary_expression)(input)?;
+    loop {
+        match postfix(rest) {
+            Ok((r, op)) => {
+                let kind = match op {
+                    Postfix::Call(args, kwargs) => {
+                        ExpressionKind::FunCall(FunCallExpression::new(expr, args, kwargs))
+                    }
+                    Postfix::Index(arg) => ExpressionKind::Index(IndexExpression::new(expr, arg)),
+                    Postfix::Attribute(name) => {
+                        ExpressionKind::Attribute(AttributeExpression::new(expr, name))
+                    }
+                };
+                expr = Expression::new(kind, source_span(input, r));
+                rest = r;
+            }
+            Err(nom::Err::Error(_)) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((rest, expr))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    Left,
+    Right,
+}
+
+// a binary operator of the expression grammar. operators with a higher
+// precedence bind tighter.
+#[derive(Debug, Clone)]
+pub struct BinaryOperatorInfo {
+    pub token: &'static str,
+    pub op: BinaryOperator,
+    pub precedence: u8,
+    pub associativity: Associativity,
+}
+
+const fn binary(
+    token: &'static str,
+    op: BinaryOperator,
+    precedence: u8,
+    associativity: Associativity,
+) -> BinaryOperatorInfo {
+    BinaryOperatorInfo {
+        token,
+        op,
+        precedence,
+        associativity,
+    }
+}
+
+// tokens are tried in order, so a token must come before any token it starts
+// with, e.g. `<=` before `<`
+pub const BINARY_OPERATORS: &[BinaryOperatorInfo] = &[
+    binary("or", BinaryOperator::Or, 1, Associativity::Left),
+    binary("and", BinaryOperator::And, 2, Associativity::Left),
+    binary("==", BinaryOperator::Eq, 4, Associativity::Left),
+    binary("!=", BinaryOperator::Neq, 4, Associativity::Left),
+    binary("<=", BinaryOperator::Le, 4, Associativity::Left),
+    binary(">=", BinaryOperator::Ge, 4, Associativity::Left),
+    binary("<", BinaryOperator::Lt, 4, Associativity::Left),
+    binary(">", BinaryOperator::Gt, 4, Associativity::Left),
+    binary("+", BinaryOperator::Plus, 5, Associativity::Left),
+    binary("-", BinaryOperator::Minus, 5, Associativity::Left),
+    binary("*", BinaryOperator::Times, 6, Associativity::Left),
+    binary("/", BinaryOperator::Divide, 6, Associativity::Left),
+    binary("%", BinaryOperator::Modulo, 6, Associativity::Left),
+];
+
+// a prefix operator. its operand only takes binary operators of a higher
+// precedence, so `not a == b` is `not (a == b)` while `-a * b` is `(-a) * b`.
+#[derive(Debug, Clone)]
+pub struct PrefixOperatorInfo {
+    pub token: &'static str,
+    pub op: UnaryOperator,
+    pub precedence: u8,
+}
+
+pub const PREFIX_OPERATORS: &[PrefixOperatorInfo] = &[
+    PrefixOperatorInfo {
+        token: "not",
+        op: UnaryOperator::Not,
+        precedence: 3,
+    },
+    PrefixOperatorInfo {
+        token: "-",
+        op: UnaryOperator::Neg,
+        precedence: 7,
+    },
+];
+
+// matches an operator token. word operators must not run into a following name.
+fn operator_token<'a>(token: &'static str) -> impl FnMut(Span<'a>) -> Result<'a, Span<'a>> {
+    move |input| {
+        if token.chars().all(char::is_alphabetic) {
+            keyword(token)(input)
+        } else {
+            tag(token)(input)
+        }
+    }
+}
+
+fn binary_operator(input: Span) -> Result<&'static BinaryOperatorInfo> {
+    for info in BINARY_OPERATORS {
+        if let Ok((rest, _)) = operator_token(info.token)(input) {
+            return Ok((rest, info));
+        }
+    }
+    Err(nom::Err::Error(nom::error::VerboseError::from_error_kind(
+        input,
+        nom::error::ErrorKind::Tag,
+    )))
+}
+
+// a call expression, or a prefix operator applied to its operand
+fn prefix_expression(input: Span) -> Result<Expression> {
+    for info in PREFIX_OPERATORS {
+        if let Ok((rest, _)) = operator_token(info.token)(input) {
+            let (rest, operand) = seq::preceded(
+                white_no_newline0,
+                comb::cut(|i| operator_expression(i, info.precedence)),
+            )(rest)?;
+            let kind = ExpressionKind::Unary(UnaryExpression::new(info.op.clone(), operand));
+            return Ok((rest, Expression::new(kind, source_span(input, rest))));
+        }
+    }
+    call_expression(input)
+}
+
+// precedence climbing: parses operands joined by binary operators whose
+// precedence is at least `min_precedence`
+fn operator_expression(input: Span, min_precedence: u8) -> Result<Expression> {
+    let (mut rest, mut left) = prefix_expression(input)?;
+    loop {
+        let (after_op, info) = match seq::preceded(white_no_newline0, binary_operator)(rest) {
+            Ok(found) => found,
+            Err(nom::Err::Error(_)) => break,
+            Err(e) => return Err(e),
+        };
+        if info.precedence < min_precedence {
+            break;
+        }
+        // the right operand of a left-associative operator stops at the next
+        // operator of the same precedence, which then takes the whole left side
+        let right_precedence = match info.associativity {
+            Associativity::Left => info.precedence + 1,
+            Associativity::Right => info.precedence,
+        };
+        let (r, right) = seq::preceded(
+            white_no_newline0,
+            comb::cut(|i| operator_expression(i, right_precedence)),
+        )(after_op)?;
+        left = binary_node(info.op.clone(), left, right);
+        rest = r;
+    }
+    Ok((rest, left))
 }
 
 pub fn expression(input: Span) -> Result<Expression> {
-    context("expression", cmp_expression)(input)
+    context("expression", |i| operator_expression(i, 0))(input)
 }
 
-pub fn arg_list(input: Span) -> Result<Vec<Expression>> {
-    let sep = seq::tuple((cp::space0, tag(","), cp::space0));
-    context(
-        "arg_list",
-        comb::map(separated_list0(sep, expression), |params| {
-            params.into_iter().collect()
-        }),
+// start of a `name = value` argument, told apart from `name == value`
+fn keyword_prefix(input: Span) -> Result<Span> {
+    seq::terminated(
+        ident,
+        seq::tuple((white_no_newline0, tag("="), comb::not(tag("=")))),
     )(input)
 }
 
-pub fn arg_list0(input: Span) -> Result<Vec<Expression>> {
+// the name is kept as a span so that a repeated one can be reported where it is
+fn keyword_argument(input: Span) -> Result<(Span, Expression)> {
+    seq::pair(
+        keyword_prefix,
+        seq::preceded(white_no_newline0, comb::cut(expression)),
+    )(input)
+}
+
+// `value` or `*items`
+fn positional_argument(input: Span) -> Result<Argument> {
+    let spread = comb::map(
+        seq::preceded(
+            seq::pair(tag("*"), white_no_newline0),
+            comb::cut(expression),
+        ),
+        Argument::Spread,
+    );
+    let value = comb::map(
+        seq::preceded(comb::not(keyword_prefix), expression),
+        Argument::Value,
+    );
+    branch::alt((spread, value))(input)
+}
+
+type CallArgs = (Vec<Argument>, Vec<(String, Expression)>);
+
+// positional arguments followed by keyword arguments, e.g. `1, 2, c = 3`
+pub fn arg_list(input: Span) -> Result<CallArgs> {
+    let sep = || seq::tuple((white_no_newline0, tag(","), white_no_newline0));
+    let (rest, args) = context("arg_list", separated_list0(sep(), positional_argument))(input)?;
+
+    let first_kwarg = |input| {
+        if args.is_empty() {
+            keyword_argument(input)
+        } else {
+            seq::preceded(sep(), keyword_argument)(input)
+        }
+    };
+    let (rest, first) = comb::opt(first_kwarg)(rest)?;
+    let Some(first) = first else {
+        return Ok((rest, (args, vec![])));
+    };
+    // no positional argument may follow a keyword argument
+    let (rest, more) = many0(seq::preceded(
+        sep(),
+        comb::cut(context("keyword_argument", keyword_argument)),
+    ))(rest)?;
+    let mut kwargs: Vec<(String, Expression)> = Vec::new();
+    for (name, value) in std::iter::once(first).chain(more) {
+        if kwargs.iter().any(|(earlier, _)| earlier == name.fragment()) {
+            return Err(nom::Err::Failure(nom::error::VerboseError::add_context(
+                name,
+                "duplicate_keyword_argument",
+                nom::error::VerboseError::from_error_kind(name, nom::error::ErrorKind::Verify),
+            )));
+        }
+        kwargs.push((name.to_string(), value));
+    }
+    Ok((rest, (args, kwargs)))
+}
+
+pub fn arg_list0(input: Span) -> Result<CallArgs> {
     // arg_list surrounded by spaces
     let arg_list_spaced = comb::map(
-        seq::tuple((cp::space0, arg_list, cp::space0)),
+        seq::tuple((white_no_newline0, arg_list, white_no_newline0)),
         |(_, args, _)| args,
     );
     // empty list
-    let empty_list = comb::map(cp::space0, |_| vec![]);
+    let empty_list = comb::map(white_no_newline0, |_| (vec![], vec![]));
     context("arg_list0", branch::alt((arg_list_spaced, empty_list)))(input)
 }
 
 pub fn expression_stmt(input: Span) -> Result<Statement> {
     context(
         "expression_stmt",
-        comb::map(expression, |e| Statement::Expression(e)),
+        statement_node(comb::map(expression, StatementKind::Expression)),
     )(input)
 }
 
 pub fn assignment(input: Span) -> Result<Statement> {
     let assign = context(
         "assignment",
-        comb::map(
-            seq::tuple((ident, cp::space0, tag("="), cp::space0, expression)),
-            |(name, _, _, _, expr)| {
-                Statement::Assignment(AssignmentStatement::new(name.to_string(), expr))
+        statement_node(comb::map(
+            seq::tuple((
+                ident,
+                white_no_newline0,
+                seq::terminated(tag("="), comb::not(tag("="))),
+                comb::cut(seq::preceded(white_no_newline0, expression)),
+            )),
+            |(name, _, _, expr)| {
+                StatementKind::Assignment(AssignmentStatement::new(name.to_string(), expr))
             },
-        ),
+        )),
     );
 
     let indexed_assign = context(
         "indexed_assignment",
-        comb::map(
+        statement_node(comb::map_opt(
             seq::tuple((
-                indexing_expression,
-                cp::space0,
+                call_expression,
+                white_no_newline0,
                 tag("="),
-                cp::space0,
+                white_no_newline0,
                 expression,
             )),
-            |(indexing_exp, _, _, _, expr)| {
-                Statement::ObjectAssignment(ObjectAssignmentStatement::new(
-                    indexing_exp.callee().clone(),
-                    indexing_exp.arg().clone(),
-                    expr,
-                ))
+            // only an indexing or an attribute can be the target of an assignment
+            |(target, _, _, _, expr)| match target.kind() {
+                ExpressionKind::Index(index) => Some(StatementKind::ObjectAssignment(
+                    ObjectAssignmentStatement::new(
+                        index.callee().clone(),
+                        index.arg().clone(),
+                        expr,
+                    ),
+                )),
+                ExpressionKind::Attribute(attr) => Some(StatementKind::AttributeAssignment(
+                    AttributeAssignmentStatement::new(
+                        attr.object().clone(),
+                        attr.name().to_string(),
+                        expr,
+                    ),
+                )),
+                _ => None,
             },
-        ),
+        )),
     );
 
     branch::alt((assign, indexed_assign))(input)
@@ -303,28 +848,42 @@ pub fn assignment(input: Span) -> Result<Statement> {
 pub fn block_stmt(input: Span) -> Result<Statement> {
     context(
         "block_stmt",
-        comb::map(
-            seq::tuple((tag("do"), white1, stmt_list, white1, tag("end"))),
-            |(_, _, stmts, _, _)| Statement::Block(stmts),
-        ),
+        statement_node(comb::map(
+            seq::preceded(
+                keyword("do"),
+                comb::cut(seq::tuple((white0, stmt_list, white0, keyword("end")))),
+            ),
+            |(_, stmts, _, _)| StatementKind::Block(stmts),
+        )),
     )(input)
 }
 
 pub fn return_stmt(input: Span) -> Result<Statement> {
     let value_return = comb::map(
-        seq::tuple((tag("return"), white1, expression)),
-        |(_, _, expr)| Statement::Return(ReturnStatement::new(expr)),
+        seq::tuple((keyword("return"), white1, expression)),
+        |(_, _, expr)| StatementKind::Return(ReturnStatement::new(expr)),
     );
-    let no_value_return = comb::map(tag("return"), |_| {
-        Statement::Return(ReturnStatement::new_null())
+    let no_value_return = comb::map(keyword("return"), |_| {
+        StatementKind::Return(ReturnStatement::new_null())
     });
-    context("return_stmt", branch::alt((value_return, no_value_return)))(input)
+    context(
+        "return_stmt",
+        statement_node(branch::alt((value_return, no_value_return))),
+    )(input)
+}
+
+// `break` or `continue`. whether it is inside a loop is checked by the compiler.
+pub fn loop_control_stmt(input: Span) -> Result<Statement> {
+    statement_node(branch::alt((
+        comb::map(keyword("break"), |_| StatementKind::Break),
+        comb::map(keyword("continue"), |_| StatementKind::Continue),
+    )))(input)
 }
 
 pub fn stmt_list(input: Span) -> Result<Vec<Statement>> {
     context(
         "stmt_list",
-        comb::map(separated_list0(cp::multispace1, statement), |stmts| {
+        comb::map(separated_list0(white1, statement), |stmts| {
             stmts.into_iter().filter_map(|s| Some(s)).collect()
         }),
     )(input)
@@ -333,112 +892,287 @@ pub fn stmt_list(input: Span) -> Result<Vec<Statement>> {
 pub fn stmt_list1(input: Span) -> Result<Vec<Statement>> {
     context(
         "stmt_list1",
-        comb::map(separated_list1(cp::multispace1, statement), |stmts| {
+        comb::map(separated_list1(white1, statement), |stmts| {
             stmts.into_iter().filter_map(|s| Some(s)).collect()
         }),
     )(input)
 }
 
-pub fn conditional_stmt(input: Span) -> Result<Statement> {
-    let no_else = comb::map(
-        seq::tuple((
-            tag("if"),
-            white_no_newline1,
-            expression,
-            white_no_newline1,
-            block_stmt,
+// statements of an `if` branch, up to the `elif`, `else` or `end` after them
+fn branch_body(input: Span) -> Result<Statement> {
+    seq::preceded(
+        white0,
+        statement_node(comb::map(stmt_list, StatementKind::Block)),
+    )(input)
+}
+
+// what follows the statements of a branch: another branch, the final
+// `else` branch, or `end`. the older `end else do ... end` form is still accepted.
+fn conditional_tail(input: Span) -> Result<Option<Statement>> {
+    let elif = statement_node(seq::preceded(
+        keyword("elif"),
+        comb::cut(conditional_branch),
+    ));
+    let else_clause = seq::delimited(
+        keyword("else"),
+        comb::cut(branch_body),
+        comb::cut(seq::pair(white0, keyword("end"))),
+    );
+    let end = seq::preceded(
+        keyword("end"),
+        comb::opt(seq::preceded(
+            seq::pair(white0, keyword("else")),
+            comb::cut(seq::preceded(white0, block_stmt)),
         )),
-        |(_, _, cond, _, body)| {
-            Statement::Conditional(ConditionalStatement::new_no_else(cond, body))
-        },
     );
+    branch::alt((comb::map(elif, Some), comb::map(else_clause, Some), end))(input)
+}
 
-    let has_else = comb::map(
+// `cond do stmts` and the rest of the chain. `elif` branches become an `if`
+// nested in the else branch.
+fn conditional_branch(input: Span) -> Result<StatementKind> {
+    comb::map(
         seq::tuple((
-            tag("if"),
-            white_no_newline1,
+            white1,
             expression,
-            white_no_newline1,
-            block_stmt,
-            white_no_newline1,
-            tag("else"),
-            white_no_newline1,
-            block_stmt,
+            white0,
+            keyword("do"),
+            branch_body,
+            white0,
+            conditional_tail,
         )),
-        |(_, _, cond, _, body, _, _, _, else_body)| {
-            Statement::Conditional(ConditionalStatement::new(cond, body, else_body))
+        |(_, cond, _, _, body, _, otherwise)| match otherwise {
+            None => StatementKind::Conditional(ConditionalStatement::new_no_else(cond, body)),
+            Some(otherwise) => {
+                StatementKind::Conditional(ConditionalStatement::new(cond, body, otherwise))
+            }
         },
-    );
+    )(input)
+}
 
-    context("conditional_stmt", branch::alt((has_else, no_else)))(input)
+pub fn conditional_stmt(input: Span) -> Result<Statement> {
+    context(
+        "conditional_stmt",
+        statement_node(seq::preceded(keyword("if"), comb::cut(conditional_branch))),
+    )(input)
 }
 
 pub fn while_stmt(input: Span) -> Result<Statement> {
     context(
         "while_stmt",
-        comb::map(
-            seq::tuple((
-                tag("while"),
-                white_no_newline1,
-                expression,
-                white_no_newline1,
-                block_stmt,
-            )),
-            |(_, _, cond, _, body)| Statement::While(WhileStatement::new(cond, body)),
-        ),
+        statement_node(comb::map(
+            seq::preceded(
+                keyword("while"),
+                comb::cut(seq::tuple((white1, expression, white0, block_stmt))),
+            ),
+            |(_, cond, _, body)| StatementKind::While(WhileStatement::new(cond, body)),
+        )),
     )(input)
 }
 
-pub fn funcdef_stmt(input: Span) -> Result<Statement> {
-    let tup = seq::tuple((
-        tag("def"),
-        white_no_newline1,
-        ident,
-        white_no_newline0,
-        tag("("),
-        cp::multispace0,
-        param_list,
-        cp::multispace0,
-        tag(")"),
-        white1,
-        block_stmt,
-    ));
+pub fn for_stmt(input: Span) -> Result<Statement> {
+    context(
+        "for_stmt",
+        statement_node(comb::map(
+            seq::preceded(
+                keyword("for"),
+                comb::cut(seq::tuple((
+                    white1,
+                    ident,
+                    white1,
+                    keyword("in"),
+                    white1,
+                    expression,
+                    white0,
+                    block_stmt,
+                ))),
+            ),
+            |(_, name, _, _, _, iterable, _, body)| {
+                StatementKind::For(ForStatement::new(name.to_string(), iterable, body))
+            },
+        )),
+    )(input)
+}
+
+fn funcdef(input: Span) -> Result<FuncDefStatement> {
+    let tup = seq::preceded(
+        keyword("def"),
+        comb::cut(seq::tuple((
+            white_no_newline1,
+            ident,
+            white_no_newline0,
+            expect("("),
+            white0,
+            param_list,
+            white0,
+            expect(")"),
+            white1,
+            block_stmt,
+        ))),
+    );
     context(
         "funcdef_stmt",
-        comb::map(tup, |(_, _, name, _, _, _, params, _, _, _, body)| {
-            Statement::FuncDef(FuncDefStatement::new(name.to_string(), params, body))
+        comb::map(tup, |(_, name, _, _, _, params, _, _, _, body)| {
+            FuncDefStatement::new(name.to_string(), params, body)
         }),
     )(input)
 }
 
-pub fn param_list(input: Span) -> Result<Vec<String>> {
-    let sep = seq::tuple((cp::multispace0, tag(","), cp::multispace0));
+pub fn funcdef_stmt(input: Span) -> Result<Statement> {
+    statement_node(comb::map(funcdef, StatementKind::FuncDef))(input)
+}
+
+// `class Name do ... end` or `class Name(Parent) do ... end`.
+// the body holds method definitions only.
+pub fn classdef_stmt(input: Span) -> Result<Statement> {
+    let parent = seq::delimited(
+        seq::pair(white_no_newline0, tag("(")),
+        comb::cut(seq::delimited(white0, expression, white0)),
+        expect(")"),
+    );
+    let tup = seq::preceded(
+        keyword("class"),
+        comb::cut(seq::tuple((
+            white_no_newline1,
+            ident,
+            comb::opt(parent),
+            white1,
+            keyword("do"),
+            white0,
+            separated_list0(white1, funcdef),
+            white0,
+            keyword("end"),
+        ))),
+    );
     context(
-        "param_list",
-        comb::map(separated_list0(sep, ident), |params| {
-            params.into_iter().map(|s| s.to_string()).collect()
-        }),
+        "classdef_stmt",
+        statement_node(comb::map(
+            tup,
+            |(_, name, parent, _, _, _, methods, _, _)| {
+                StatementKind::ClassDef(ClassDefStatement::new(name.to_string(), parent, methods))
+            },
+        )),
     )(input)
 }
 
+// `a, b = 2, *rest, c = 3`. positional parameters after one with a default value
+// need a default too. `*rest` collects the extra positional arguments, and the
+// parameters after it (or after a bare `*`) can only be passed by keyword.
+pub fn param_list(input: Span) -> Result<Vec<Parameter>> {
+    context("param_list", param_entries)(input)
+}
+
+// parameter names must be unique, and a bare `*` must be followed by a
+// keyword-only parameter. both are reported at the offending parameter.
+fn param_entries(input: Span) -> Result<Vec<Parameter>> {
+    let mut params: Vec<Parameter> = Vec::new();
+    let mut keyword_only = false;
+    // a bare `*` that no keyword-only parameter has followed yet
+    let mut bare_star: Option<Span> = None;
+    let mut rest = input;
+    loop {
+        // entries after the first one are preceded by a comma
+        let entry = if params.is_empty() && !keyword_only {
+            rest
+        } else {
+            match seq::tuple((white0, tag(","), white0))(rest) {
+                Ok((r, _)) => r,
+                Err(nom::Err::Error(_)) => break,
+                Err(e) => return Err(e),
+            }
+        };
+
+        if !keyword_only {
+            let star: Result<Span> = tag("*")(entry);
+            if let Ok((r, star)) = star {
+                keyword_only = true;
+                rest = r;
+                let rest_param: Result<Span> = seq::preceded(white_no_newline0, ident)(r);
+                match rest_param {
+                    Ok((r, name)) => {
+                        check_unique_param(&params, name)?;
+                        params.push(Parameter::new_rest(name.to_string()));
+                        rest = r;
+                    }
+                    Err(_) => bare_star = Some(star),
+                }
+                continue;
+            }
+        }
+
+        let (r, name) = match ident(entry) {
+            Ok(ok) => ok,
+            Err(nom::Err::Error(_)) => break,
+            Err(e) => return Err(e),
+        };
+        check_unique_param(&params, name)?;
+        bare_star = None;
+        let needs_default = !keyword_only && params.last().is_some_and(|p| p.default().is_some());
+        let (r, default) = if needs_default {
+            comb::map(
+                seq::preceded(
+                    comb::cut(seq::tuple((white0, expect("="), white0))),
+                    comb::cut(expression),
+                ),
+                Some,
+            )(r)?
+        } else {
+            comb::opt(seq::preceded(
+                seq::tuple((white0, tag("="), white0)),
+                comb::cut(expression),
+            ))(r)?
+        };
+        params.push(Parameter::new(name.to_string(), default, keyword_only));
+        rest = r;
+    }
+    if let Some(star) = bare_star {
+        return Err(nom::Err::Failure(nom::error::VerboseError::add_context(
+            star,
+            "bare_star",
+            nom::error::VerboseError::from_error_kind(star, nom::error::ErrorKind::Verify),
+        )));
+    }
+    Ok((rest, params))
+}
+
+fn check_unique_param<'a>(params: &[Parameter], name: Span<'a>) -> Result<'a, ()> {
+    if params.iter().any(|p| p.name() == *name.fragment()) {
+        return Err(nom::Err::Failure(nom::error::VerboseError::add_context(
+            name,
+            "duplicate_parameter",
+            nom::error::VerboseError::from_error_kind(name, nom::error::ErrorKind::Verify),
+        )));
+    }
+    Ok((name, ()))
+}
+
 pub fn statement(input: Span) -> Result<Statement> {
     context(
         "statement",
         branch::alt((
+            keyword_assignment,
             block_stmt,
             funcdef_stmt,
+            classdef_stmt,
             conditional_stmt,
             while_stmt,
+            for_stmt,
             assignment,
             return_stmt,
+            loop_control_stmt,
             expression_stmt,
         )),
     )(input)
 }
 
 pub fn program(input: Span) -> Result<Vec<Statement>> {
-    // comb::map(stmt_list1, |stmts| {
-    //     stmts.into_iter().filter_map(|s| Some(s)).collect()
-    // })(input)
-    context("program", comb::map(block_stmt, |stmt| vec![stmt]))(input)
+    // a script is a sequence of statements, optionally surrounded by blank lines
+    context(
+        "program",
+        seq::delimited(
+            white0,
+            stmt_list,
+            seq::terminated(white0, context("eof", comb::eof)),
+        ),
+    )(input)
 }
@@ -0,0 +1,100 @@
+use crate::object::{ObjectPtr, Value};
+
+use super::{RuntimeError, RuntimeErrorKind, VM};
+
+// containers nested deeper than this are shown as `...`. containers that hold
+// themselves are cut off before this, as soon as they repeat on the path.
+const MAX_DISPLAY_DEPTH: usize = 16;
+
+impl VM {
+    // text shown by println, str and string interpolation
+    pub fn display_string(&self, value: &Value) -> Result<String, RuntimeError> {
+        self.display_nested(value, &mut Vec::new())
+    }
+
+    // strings inside containers are quoted so that `["a"]` can be told apart from `[a]`.
+    // `path` holds the addresses of the containers being displayed around `value`.
+    fn display_nested(
+        &self,
+        value: &Value,
+        path: &mut Vec<*const Value>,
+    ) -> Result<String, RuntimeError> {
+        let depth = path.len();
+        let elided = depth >= MAX_DISPLAY_DEPTH || path.contains(&(value as *const Value));
+        let s = match value {
+            Value::String(s) if depth > 0 => format!("{:?}", s),
+            Value::String(s) => s.clone(),
+            Value::Integer(i) => i.to_string(),
+            Value::Float(f) => format!("{:?}", f),
+            Value::Boolean(b) => b.to_string(),
+            Value::Invalid => {
+                return Err(self.runtime_error(
+                    RuntimeErrorKind::UninitializedValue,
+                    "access to uninitialized value",
+                ))
+            }
+            Value::Null => "null".to_string(),
+            Value::Function(_) | Value::Closure(_) => "<function object>".to_string(),
+            Value::Upvalue(_) => "<upvalue>".to_string(),
+            Value::List(_) if elided => "[...]".to_string(),
+            Value::List(elements) => {
+                let parts = self.display_elements(value, elements, path)?;
+                format!("[{}]", parts.join(", "))
+            }
+            Value::Tuple(_) if elided => "(...)".to_string(),
+            Value::Tuple(elements) => {
+                let parts = self.display_elements(value, elements, path)?;
+                // a one-element tuple keeps its comma, as in the literal syntax
+                if parts.len() == 1 {
+                    format!("({},)", parts[0])
+                } else {
+                    format!("({})", parts.join(", "))
+                }
+            }
+            Value::Dict(_) if elided => "{...}".to_string(),
+            Value::Dict(map) => {
+                path.push(value);
+                let parts = map
+                    .entries()
+                    .iter()
+                    .map(|(k, v)| {
+                        Ok(format!(
+                            "{}: {}",
+                            self.display_nested(k.get().value(), path)?,
+                            self.display_nested(v.get().value(), path)?
+                        ))
+                    })
+                    .collect::<Result<Vec<_>, RuntimeError>>();
+                path.pop();
+                format!("{{{}}}", parts?.join(", "))
+            }
+            Value::Class(class) => format!("<class {}>", class.name()),
+            Value::Instance(instance) => format!("<{} object>", instance.class_name()),
+            Value::BoundMethod(_) => "<bound method>".to_string(),
+            Value::Range(range) => format!(
+                "range({}, {}, {})",
+                range.start(),
+                range.stop(),
+                range.step()
+            ),
+            Value::Iterator(_) => "<iterator>".to_string(),
+        };
+        Ok(s)
+    }
+
+    // displays the elements of the list or tuple `container`
+    fn display_elements(
+        &self,
+        container: &Value,
+        elements: &[ObjectPtr],
+        path: &mut Vec<*const Value>,
+    ) -> Result<Vec<String>, RuntimeError> {
+        path.push(container);
+        let parts = elements
+            .iter()
+            .map(|e| self.display_nested(e.get().value(), path))
+            .collect();
+        path.pop();
+        parts
+    }
+}
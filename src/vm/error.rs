@@ -0,0 +1,135 @@
+use std::fmt;
+
+use crate::{ast::SourceSpan, opcode::Opcode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeErrorKind {
+    InvalidOperands,
+    DivisionByZero,
+    InvalidCondition,
+    InvalidExitCode,
+    NotCallable,
+    InvalidArgumentCount,
+    ReturnWithoutCall,
+    UninitializedValue,
+    InvalidIndex,
+    IndexOutOfBounds,
+    InvalidKey,
+    KeyNotFound,
+    AttributeNotFound,
+    NotIterable,
+    StackOverflow,
+    UnimplementedOpcode,
+}
+
+impl fmt::Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            RuntimeErrorKind::InvalidOperands => "invalid operands",
+            RuntimeErrorKind::DivisionByZero => "division by zero",
+            RuntimeErrorKind::InvalidCondition => "invalid condition",
+            RuntimeErrorKind::InvalidExitCode => "invalid exit code",
+            RuntimeErrorKind::NotCallable => "not callable",
+            RuntimeErrorKind::InvalidArgumentCount => "invalid number of arguments",
+            RuntimeErrorKind::ReturnWithoutCall => "return without call",
+            RuntimeErrorKind::UninitializedValue => "uninitialized value",
+            RuntimeErrorKind::InvalidIndex => "invalid index",
+            RuntimeErrorKind::IndexOutOfBounds => "index out of bounds",
+            RuntimeErrorKind::InvalidKey => "invalid key",
+            RuntimeErrorKind::KeyNotFound => "key not found",
+            RuntimeErrorKind::AttributeNotFound => "attribute not found",
+            RuntimeErrorKind::NotIterable => "not iterable",
+            RuntimeErrorKind::StackOverflow => "stack overflow",
+            RuntimeErrorKind::UnimplementedOpcode => "unimplemented opcode",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// error raised while executing bytecode.
+// pc and opcode point to the instruction that failed.
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    kind: RuntimeErrorKind,
+    message: String,
+    pc: usize,
+    opcode: Option<Box<Opcode>>,
+    span: Option<Box<SourceSpan>>,
+    traceback: Vec<Option<SourceSpan>>,
+}
+
+impl RuntimeError {
+    pub fn new(
+        kind: RuntimeErrorKind,
+        message: impl Into<String>,
+        pc: usize,
+        opcode: Option<Opcode>,
+    ) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            pc,
+            opcode: opcode.map(Box::new),
+            span: None,
+            traceback: vec![],
+        }
+    }
+
+    // attaches the source location of the faulting instruction and of the
+    // call sites leading to it (innermost first)
+    pub fn with_location(
+        mut self,
+        span: Option<SourceSpan>,
+        traceback: Vec<Option<SourceSpan>>,
+    ) -> Self {
+        self.span = span.map(Box::new);
+        self.traceback = traceback;
+        self
+    }
+
+    pub fn kind(&self) -> RuntimeErrorKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn opcode(&self) -> Option<&Opcode> {
+        self.opcode.as_deref()
+    }
+
+    pub fn span(&self) -> Option<SourceSpan> {
+        self.span.as_deref().copied()
+    }
+
+    pub fn traceback(&self) -> &[Option<SourceSpan>] {
+        &self.traceback
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "runtime error ({}): {} at pc {}",
+            self.kind, self.message, self.pc
+        )?;
+        if let Some(op) = &self.opcode {
+            write!(f, " ({:?})", op)?;
+        }
+        if let Some(span) = &self.span {
+            write!(f, " at line {}", span.start())?;
+        }
+        for call_site in self.traceback.iter().flatten() {
+            write!(f, "\n  called from line {}", call_site.start())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
@@ -1,25 +1,43 @@
+mod display;
+mod error;
+
 use std::collections::HashMap;
 
+pub use error::{RuntimeError, RuntimeErrorKind};
+
 use crate::{
-    object::{FunctionAddress, FunctionInfo, Object, Value},
-    opcode::Opcode,
+    compiler::{LineTable, Program},
+    object::{
+        BoundMethod, BuiltinIterator, ClassObject, Closure, FunctionAddress, FunctionInfo,
+        Instance, NativeFunction, Object, Signature, Upvalue, Value,
+    },
+    opcode::{Capture, Opcode},
 };
 
+use crate::object::internal::hashmap::HashMap as MyHashMap;
 use crate::object::ObjectPtr;
 
+// nested calls allowed before a stack overflow is reported
+const MAX_CALL_DEPTH: usize = 1024;
+
 #[derive(Debug)]
 pub struct VM {
     stack: Vec<ObjectPtr>,
     stack_top: usize,
 
     opcode: Vec<Opcode>,
+    line_table: LineTable,
     pc: usize,
+    exit_code: Option<i64>,
 
     stack_frames: Vec<LinearMemory>,
     stack_frame_top: usize,
 
     globals: NameMemory,
 
+    // upvalues still pointing into a live frame, closed when that frame returns
+    open_upvalues: Vec<ObjectPtr>,
+
     gc: crate::object::GCSystem,
 }
 
@@ -32,13 +50,17 @@ impl VM {
             stack_top: 0,
 
             opcode: vec![],
+            line_table: LineTable::default(),
             pc: 0,
+            exit_code: None,
 
             stack_frames: vec![LinearMemory::new(invalid_obj.clone())],
             stack_frame_top: 0,
 
             globals: NameMemory::new(invalid_obj),
 
+            open_upvalues: Vec::new(),
+
             gc,
         };
         vm
@@ -55,10 +77,7 @@ impl VM {
     }
 
     pub fn gc_mark(&mut self) {
-        let mut roots = self.collect_objptr();
-        for sf in self.stack_frames.iter_mut() {
-            roots.extend(sf.collect_objptr().iter().cloned().collect::<Vec<_>>());
-        }
+        let roots = self.collect_roots();
         for root in roots {
             self.gc.mark_all(root);
         }
@@ -72,12 +91,18 @@ impl VM {
         self.stack.clone()
     }
 
-    pub fn alloc_object(&mut self, object: Object) -> ObjectPtr {
+    fn collect_roots(&mut self) -> Vec<ObjectPtr> {
         let mut roots = self.collect_objptr();
         for sf in self.stack_frames.iter_mut() {
             roots.extend(sf.collect_objptr());
         }
         roots.extend(self.globals.collect_objptr());
+        roots.extend(self.open_upvalues.iter().cloned());
+        roots
+    }
+
+    pub fn alloc_object(&mut self, object: Object) -> ObjectPtr {
+        let mut roots = self.collect_roots();
         self.gc.new_object(object, &mut roots)
     }
 
@@ -94,11 +119,25 @@ impl VM {
         }
     }
 
-    fn push_stackframe(&mut self, return_pc: usize) {
+    fn push_stackframe(&mut self, return_pc: usize) -> Result<(), RuntimeError> {
+        if self.stack_frames.len() >= MAX_CALL_DEPTH {
+            return Err(self.runtime_error(RuntimeErrorKind::StackOverflow, "stack overflow"));
+        }
         self.stack_frame_top += 1;
         let invalid_obj = self.alloc_object(Object::make_invalid());
         self.stack_frames
             .push(LinearMemory::new_with_return(invalid_obj, return_pc));
+        Ok(())
+    }
+
+    // pushes onto the operand stack, which has a fixed size
+    fn push(&mut self, value: ObjectPtr) -> Result<(), RuntimeError> {
+        if self.stack_top >= self.stack.len() {
+            return Err(self.runtime_error(RuntimeErrorKind::StackOverflow, "stack overflow"));
+        }
+        self.stack[self.stack_top] = value;
+        self.stack_top += 1;
+        Ok(())
     }
 
     fn pop_stackframe(&mut self) {
@@ -107,89 +146,945 @@ impl VM {
     }
 
     pub fn set_code(&mut self, code: Vec<Opcode>) {
+        // drop whatever a failed run left behind. the top-level frame and the
+        // globals are kept so that a later program can use them.
+        for frame in (1..self.stack_frames.len()).rev() {
+            self.close_upvalues(frame);
+        }
+        self.stack_frames.truncate(1);
+        self.stack_frame_top = 0;
+        self.stack_top = 0;
+
         self.opcode = code;
+        self.line_table = LineTable::default();
+        self.pc = 0;
+        self.exit_code = None;
+    }
+
+    pub fn set_program(&mut self, program: Program) {
+        let (code, line_table) = program.into_parts();
+        self.set_code(code);
+        self.line_table = line_table;
     }
 
     fn current_stack_frame(&mut self) -> &mut LinearMemory {
         &mut self.stack_frames[self.stack_frame_top]
     }
 
-    fn opcode_arithmetic(&mut self, op: Opcode) {
+    pub fn runtime_error(
+        &self,
+        kind: RuntimeErrorKind,
+        message: impl Into<String>,
+    ) -> RuntimeError {
+        // call sites of the active frames, innermost first
+        let traceback = self
+            .stack_frames
+            .iter()
+            .rev()
+            .filter_map(|frame| frame.return_pc)
+            .map(|return_pc| self.line_table.lookup(return_pc - 1))
+            .collect();
+        RuntimeError::new(kind, message, self.pc, self.opcode.get(self.pc).cloned())
+            .with_location(self.line_table.lookup(self.pc), traceback)
+    }
+
+    fn integer_overflow(&self) -> RuntimeError {
+        self.runtime_error(RuntimeErrorKind::InvalidOperands, "integer overflow")
+    }
+
+    fn opcode_arithmetic(&mut self, op: Opcode) -> Result<(), RuntimeError> {
         let right = self.stack[self.stack_top - 1].clone();
         let left = self.stack[self.stack_top - 2].clone();
         self.stack_top -= 2;
 
         let result = match (left.get().value(), right.get().value()) {
             (Value::Integer(left), Value::Integer(right)) => {
-                self.alloc_object(Object::const_int(match op {
-                    Opcode::Add2 => left + right,
-                    Opcode::Sub2 => left - right,
-                    Opcode::Mul2 => left * right,
-                    Opcode::Div2 => left / right,
-                    Opcode::Mod2 => left % right,
-                    _ => panic!("invalid operands for arithmetic"),
-                }))
+                let value = match op {
+                    Opcode::Add2 => left.checked_add(*right),
+                    Opcode::Sub2 => left.checked_sub(*right),
+                    Opcode::Mul2 => left.checked_mul(*right),
+                    Opcode::Div2 | Opcode::Mod2 if *right == 0 => {
+                        return Err(self.runtime_error(
+                            RuntimeErrorKind::DivisionByZero,
+                            "integer division by zero",
+                        ));
+                    }
+                    Opcode::Div2 => left.checked_div(*right),
+                    Opcode::Mod2 => left.checked_rem(*right),
+                    _ => {
+                        return Err(self.runtime_error(
+                            RuntimeErrorKind::InvalidOperands,
+                            "invalid operands for arithmetic",
+                        ))
+                    }
+                };
+                let Some(value) = value else {
+                    return Err(self.integer_overflow());
+                };
+                self.alloc_object(Object::const_int(value))
+            }
+            // mixed int/float operands are promoted to float
+            (l, r) => match (l.as_float(), r.as_float()) {
+                (Some(left), Some(right)) => {
+                    let value = match op {
+                        Opcode::Add2 => left + right,
+                        Opcode::Sub2 => left - right,
+                        Opcode::Mul2 => left * right,
+                        Opcode::Div2 | Opcode::Mod2 if right == 0.0 => {
+                            return Err(self.runtime_error(
+                                RuntimeErrorKind::DivisionByZero,
+                                "float division by zero",
+                            ));
+                        }
+                        Opcode::Div2 => left / right,
+                        Opcode::Mod2 => left % right,
+                        _ => {
+                            return Err(self.runtime_error(
+                                RuntimeErrorKind::InvalidOperands,
+                                "invalid operands for arithmetic",
+                            ))
+                        }
+                    };
+                    self.alloc_object(Object::const_float(value))
+                }
+                _ => {
+                    return Err(self.runtime_error(
+                        RuntimeErrorKind::InvalidOperands,
+                        format!(
+                            "invalid operands for arithmetic: {} and {}",
+                            l.type_name(),
+                            r.type_name()
+                        ),
+                    ))
+                }
+            },
+        };
+
+        self.push(result)?;
+        Ok(())
+    }
+
+    fn opcode_neg(&mut self) -> Result<(), RuntimeError> {
+        let operand = self.stack[self.stack_top - 1].clone();
+        let result = match operand.get().value() {
+            Value::Integer(i) => match i.checked_neg() {
+                Some(value) => Object::const_int(value),
+                None => return Err(self.integer_overflow()),
+            },
+            Value::Float(f) => Object::const_float(-f),
+            v => {
+                return Err(self.runtime_error(
+                    RuntimeErrorKind::InvalidOperands,
+                    format!("invalid operand for negation: {}", v.type_name()),
+                ))
             }
-            _ => panic!("invalid operands for arithmetic"),
         };
+        // the operand stays on the stack (and thus rooted) until the result is allocated
+        self.stack[self.stack_top - 1] = self.alloc_object(result);
+        Ok(())
+    }
 
-        self.stack[self.stack_top] = result;
-        self.stack_top += 1;
+    // whether a condition holds, following the truthiness rules of Value::is_truthy
+    fn truthiness(&self, value: &ObjectPtr) -> Result<bool, RuntimeError> {
+        match value.get().value() {
+            Value::Invalid => Err(self.runtime_error(
+                RuntimeErrorKind::UninitializedValue,
+                "access to uninitialized value",
+            )),
+            v => Ok(v.is_truthy()),
+        }
     }
 
-    fn opcode_add(&mut self) {
+    fn opcode_add(&mut self) -> Result<(), RuntimeError> {
         let right = self.stack[self.stack_top - 1].clone();
         let left = self.stack[self.stack_top - 2].clone();
         self.stack_top -= 2;
 
         let result = match (left.get().value(), right.get().value()) {
-            (Value::Integer(left), Value::Integer(right)) => {
-                self.alloc_object(Object::const_int(left + right))
-            }
+            (Value::Integer(left), Value::Integer(right)) => match left.checked_add(*right) {
+                Some(value) => self.alloc_object(Object::const_int(value)),
+                None => return Err(self.integer_overflow()),
+            },
             (Value::String(left), Value::String(right)) => {
                 self.alloc_object(Object::const_string(format!("{}{}", left, right)))
             }
-            _ => panic!("invalid operands for arithmetic"),
+            (l, r) => match (l.as_float(), r.as_float()) {
+                (Some(left), Some(right)) => self.alloc_object(Object::const_float(left + right)),
+                _ => {
+                    return Err(self.runtime_error(
+                        RuntimeErrorKind::InvalidOperands,
+                        format!(
+                            "invalid operands for arithmetic: {} and {}",
+                            l.type_name(),
+                            r.type_name()
+                        ),
+                    ))
+                }
+            },
         };
 
-        self.stack[self.stack_top] = result;
-        self.stack_top += 1;
+        self.push(result)?;
+        Ok(())
     }
 
-    fn opcode_compare(&mut self, op: Opcode) {
+    fn opcode_compare(&mut self, op: Opcode) -> Result<(), RuntimeError> {
         let right = self.stack[self.stack_top - 1].clone();
         let left = self.stack[self.stack_top - 2].clone();
         self.stack_top -= 2;
 
+        // any two values can be tested for equality; only ordering needs compatible operands
+        if matches!(op, Opcode::Eq2 | Opcode::Neq2) {
+            let equal = left.get().value().eq_value(right.get().value());
+            let result = self.alloc_object(Object::const_bool(equal == matches!(op, Opcode::Eq2)));
+            return self.push(result);
+        }
+
         let result = match (left.get().value(), right.get().value()) {
             (Value::Integer(left), Value::Integer(right)) => {
                 self.alloc_object(Object::const_bool(match op {
-                    Opcode::Eq2 => left == right,
-                    Opcode::Neq2 => left != right,
                     Opcode::Lt2 => left < right,
                     Opcode::Gt2 => left > right,
                     Opcode::Le2 => left <= right,
                     Opcode::Ge2 => left >= right,
-                    _ => panic!("invalid operands for arithmetic"),
+                    _ => {
+                        return Err(self.runtime_error(
+                            RuntimeErrorKind::InvalidOperands,
+                            "invalid operands for comparison",
+                        ))
+                    }
                 }))
             }
-            _ => panic!("invalid operands for arithmetic"),
+            (l, r) => match (l.as_float(), r.as_float()) {
+                (Some(left), Some(right)) => self.alloc_object(Object::const_bool(match op {
+                    Opcode::Lt2 => left < right,
+                    Opcode::Gt2 => left > right,
+                    Opcode::Le2 => left <= right,
+                    Opcode::Ge2 => left >= right,
+                    _ => {
+                        return Err(self.runtime_error(
+                            RuntimeErrorKind::InvalidOperands,
+                            "invalid operands for comparison",
+                        ))
+                    }
+                })),
+                _ => {
+                    return Err(self.runtime_error(
+                        RuntimeErrorKind::InvalidOperands,
+                        format!(
+                            "invalid operands for comparison: {} and {}",
+                            l.type_name(),
+                            r.type_name()
+                        ),
+                    ))
+                }
+            },
+        };
+
+        self.push(result)?;
+        Ok(())
+    }
+
+    // resolves a (possibly negative) list or tuple index against its length
+    fn sequence_index(
+        &self,
+        sequence: &Value,
+        len: usize,
+        index: &Value,
+    ) -> Result<usize, RuntimeError> {
+        let type_name = sequence.type_name();
+        let i = match index {
+            Value::Integer(i) => *i,
+            v => {
+                return Err(self.runtime_error(
+                    RuntimeErrorKind::InvalidIndex,
+                    format!(
+                        "{} index must be an integer, got {}",
+                        type_name,
+                        v.type_name()
+                    ),
+                ))
+            }
         };
+        let resolved = if i < 0 { i + len as i64 } else { i };
+        if resolved < 0 || resolved >= len as i64 {
+            return Err(self.runtime_error(
+                RuntimeErrorKind::IndexOutOfBounds,
+                format!(
+                    "index {} out of bounds for {} of length {}",
+                    i, type_name, len
+                ),
+            ));
+        }
+        Ok(resolved as usize)
+    }
 
-        self.stack[self.stack_top] = result;
-        self.stack_top += 1;
+    // dictionary keys must be immutable so that their hash never changes
+    fn check_dict_key(&self, key: &Value) -> Result<(), RuntimeError> {
+        match key.hash() {
+            Some(_) => Ok(()),
+            None => Err(self.runtime_error(
+                RuntimeErrorKind::InvalidKey,
+                format!("unhashable dictionary key of type {}", key.type_name()),
+            )),
+        }
+    }
+
+    fn opcode_get_index(&mut self) -> Result<(), RuntimeError> {
+        let index = self.stack[self.stack_top - 1].clone();
+        let object = self.stack[self.stack_top - 2].clone();
+        self.stack_top -= 2;
+
+        let result = match object.get().value() {
+            v @ (Value::List(elements) | Value::Tuple(elements)) => {
+                let i = self.sequence_index(v, elements.len(), index.get().value())?;
+                elements[i].clone()
+            }
+            Value::Dict(map) => {
+                self.check_dict_key(index.get().value())?;
+                match map.get(index.clone()) {
+                    Some(value) => value,
+                    None => {
+                        return Err(self.runtime_error(
+                            RuntimeErrorKind::KeyNotFound,
+                            format!("key {} not found", describe_key(index.get().value())),
+                        ))
+                    }
+                }
+            }
+            v => {
+                return Err(self.runtime_error(
+                    RuntimeErrorKind::InvalidIndex,
+                    format!("{} cannot be indexed", v.type_name()),
+                ))
+            }
+        };
+
+        self.push(result)?;
+        Ok(())
+    }
+
+    fn opcode_set_index(&mut self) -> Result<(), RuntimeError> {
+        let value = self.stack[self.stack_top - 1].clone();
+        let index = self.stack[self.stack_top - 2].clone();
+        let mut object = self.stack[self.stack_top - 3].clone();
+        self.stack_top -= 3;
+
+        match object.get().value() {
+            v @ Value::List(elements) => {
+                let i = self.sequence_index(v, elements.len(), index.get().value())?;
+                if let Value::List(elements) = object.get_mut().value_mut() {
+                    elements[i] = value;
+                }
+            }
+            Value::Dict(_) => {
+                self.check_dict_key(index.get().value())?;
+                if let Value::Dict(map) = object.get_mut().value_mut() {
+                    map.put(index, value);
+                }
+            }
+            v => {
+                return Err(self.runtime_error(
+                    RuntimeErrorKind::InvalidIndex,
+                    format!("{} does not support index assignment", v.type_name()),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    // upvalue `index` of the closure running in the current frame
+    fn current_upvalue(&mut self, index: usize) -> ObjectPtr {
+        match &self.current_stack_frame().closure {
+            Some(closure) => match closure.get().value() {
+                Value::Closure(closure) => closure.upvalue(index),
+                _ => unreachable!("frame closure must be a closure"),
+            },
+            None => unreachable!("upvalue accessed outside of a closure"),
+        }
+    }
+
+    // returns the open upvalue for a local slot of the current frame,
+    // creating it unless another closure already captured the slot
+    fn capture_local(&mut self, slot: usize) -> ObjectPtr {
+        let frame = self.stack_frame_top;
+        let existing = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| open_location(upvalue) == Some((frame, slot)));
+        if let Some(upvalue) = existing {
+            return upvalue.clone();
+        }
+        let upvalue = Value::Upvalue(Upvalue::Open { frame, slot });
+        let upvalue = self.alloc_object(Object::new_from_value(upvalue));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    // moves the values of upvalues pointing into `frame` into the upvalues themselves
+    fn close_upvalues(&mut self, frame: usize) {
+        self.close_upvalues_where(frame, |_| true);
+    }
+
+    // same as close_upvalues, for the slots of `frame` accepted by `closes`
+    fn close_upvalues_where(&mut self, frame: usize, closes: impl Fn(usize) -> bool) {
+        let (closing, open): (Vec<_>, Vec<_>) = std::mem::take(&mut self.open_upvalues)
+            .into_iter()
+            .partition(|upvalue| {
+                open_location(upvalue).is_some_and(|(f, slot)| f == frame && closes(slot))
+            });
+        self.open_upvalues = open;
+        for mut upvalue in closing {
+            if let Some((_, slot)) = open_location(&upvalue) {
+                let value = self.stack_frames[frame].load(slot);
+                *upvalue.get_mut().value_mut() = Value::Upvalue(Upvalue::Closed(value));
+            }
+        }
+    }
+
+    // matches the arguments of a call to the parameters of `fun_info`, filling in
+    // default values. `implicit` leading parameters are taken by a receiver that
+    // the caller does not pass, and are not part of the returned values.
+    fn bind_arguments(
+        &self,
+        fun_info: &FunctionInfo,
+        implicit: usize,
+        args: &[ObjectPtr],
+        kwargs: &[(String, ObjectPtr)],
+    ) -> Result<Vec<BoundArgument>, RuntimeError> {
+        let signature = fun_info.signature();
+        let n_params = fun_info.n_params();
+        let n_positional = signature.n_positional().saturating_sub(implicit);
+        let rest_index = signature.rest_index();
+        if let Some(max_arity) = signature.max_arity() {
+            let max_arity = max_arity.saturating_sub(implicit);
+            let min_arity = signature.min_arity().saturating_sub(implicit);
+            if args.len() > max_arity {
+                let message = if min_arity == max_arity && max_arity + implicit == n_params {
+                    format!("expected {} arguments, got {}", max_arity, args.len())
+                } else {
+                    format!(
+                        "expected at most {} positional arguments, got {}",
+                        max_arity,
+                        args.len()
+                    )
+                };
+                return Err(self.runtime_error(RuntimeErrorKind::InvalidArgumentCount, message));
+            }
+        }
+
+        let mut bound: Vec<Option<ObjectPtr>> = vec![None; n_params];
+        for (i, arg) in args.iter().take(n_positional).enumerate() {
+            bound[implicit + i] = Some(arg.clone());
+        }
+        for (name, value) in kwargs.iter() {
+            let index = match signature.position(name) {
+                Some(index) if index >= implicit && Some(index) != rest_index => index,
+                _ => {
+                    return Err(self.runtime_error(
+                        RuntimeErrorKind::InvalidArgumentCount,
+                        format!("unexpected keyword argument `{}`", name),
+                    ))
+                }
+            };
+            if bound[index].is_some() {
+                return Err(self.runtime_error(
+                    RuntimeErrorKind::InvalidArgumentCount,
+                    format!("got multiple values for argument `{}`", name),
+                ));
+            }
+            bound[index] = Some(value.clone());
+        }
+
+        (implicit..n_params)
+            .map(|i| {
+                if Some(i) == rest_index {
+                    let extra = args.get(n_positional..).unwrap_or_default();
+                    return Ok(BoundArgument::Rest(extra.to_vec()));
+                }
+                match bound[i].clone().or_else(|| fun_info.default_value(i)) {
+                    Some(value) => Ok(BoundArgument::Value(value)),
+                    None => Err(self.runtime_error(
+                        RuntimeErrorKind::InvalidArgumentCount,
+                        format!("missing argument `{}`", signature.params()[i]),
+                    )),
+                }
+            })
+            .collect()
+    }
+
+    // calls the value below the topmost `n_args` values on the stack, or below
+    // those and a dict of keyword arguments if `has_kwargs` is set. with `spread`,
+    // the single value holds all positional arguments as a list.
+    // bound methods pass their receiver as the first argument, and classes
+    // create an instance and run their `init` method on it.
+    fn opcode_call(
+        &mut self,
+        n_args: usize,
+        has_kwargs: bool,
+        spread: bool,
+    ) -> Result<(), RuntimeError> {
+        let n_values = n_args + has_kwargs as usize;
+        let callee = self.stack[self.stack_top - n_values - 1].clone();
+        let args =
+            self.stack[self.stack_top - n_values..self.stack_top - n_values + n_args].to_vec();
+        let args = match (spread, &args[..]) {
+            (true, [list]) => match list.get().value() {
+                Value::List(elements) => elements.clone(),
+                _ => unreachable!("spread arguments are joined into a list"),
+            },
+            _ => args,
+        };
+        let kwargs: Vec<(String, ObjectPtr)> = match has_kwargs {
+            true => match self.stack[self.stack_top - 1].get().value() {
+                Value::Dict(map) => map
+                    .entries()
+                    .into_iter()
+                    .map(|(name, value)| match name.get().value() {
+                        Value::String(name) => (name.clone(), value),
+                        _ => unreachable!("keyword argument names are strings"),
+                    })
+                    .collect(),
+                _ => unreachable!("keyword arguments are passed as a dict"),
+            },
+            false => vec![],
+        };
+
+        let mut receiver = None;
+        let mut constructing = None;
+        let function = match callee.get().value() {
+            Value::Function(_) | Value::Closure(_) => callee.clone(),
+            Value::BoundMethod(method) => {
+                receiver = Some(method.receiver());
+                method.function()
+            }
+            Value::Class(class) => match class.method("init") {
+                Some(init) => {
+                    constructing = Some(callee.clone());
+                    init
+                }
+                None => {
+                    if args.len() + kwargs.len() != 0 {
+                        return Err(self.runtime_error(
+                            RuntimeErrorKind::InvalidArgumentCount,
+                            format!(
+                                "{} has no init method and takes no arguments, got {}",
+                                class.name(),
+                                args.len() + kwargs.len()
+                            ),
+                        ));
+                    }
+                    let instance = Instance::new(Some(callee.clone()));
+                    let instance =
+                        self.alloc_object(Object::new_from_value(Value::Instance(instance)));
+                    self.stack_top -= n_values;
+                    self.stack[self.stack_top - 1] = instance;
+                    self.pc += 1;
+                    return Ok(());
+                }
+            },
+            v => {
+                return Err(self.runtime_error(
+                    RuntimeErrorKind::NotCallable,
+                    format!("{} is not callable", v.type_name()),
+                ))
+            }
+        };
+        let (function, closure) = match function.get().value() {
+            Value::Closure(closure) => (closure.function(), Some(function.clone())),
+            _ => (function, None),
+        };
+        let fun_info = match function.get().value() {
+            Value::Function(fun_info) => fun_info,
+            v => {
+                return Err(self.runtime_error(
+                    RuntimeErrorKind::NotCallable,
+                    format!("{} is not callable", v.type_name()),
+                ))
+            }
+        };
+
+        // the receiver takes the first parameter slot, which the caller does not see
+        let implicit = (receiver.is_some() || constructing.is_some()) as usize;
+        // the bound values are all referenced from the stack, so they stay rooted
+        // until they are stored into the new frame
+        let bound = self.bind_arguments(fun_info, implicit, &args, &kwargs)?;
+
+        self.push_stackframe(self.pc + 1)?;
+        self.current_stack_frame().function = Some(function.clone());
+        self.current_stack_frame().closure = closure;
+        if let Some(class) = constructing {
+            // the class and arguments are still on the stack (and thus rooted) here
+            let instance = Instance::new(Some(class));
+            let instance = self.alloc_object(Object::new_from_value(Value::Instance(instance)));
+            self.current_stack_frame().store(0, instance.clone());
+            self.current_stack_frame().constructing = Some(instance);
+        }
+        if let Some(receiver) = receiver {
+            self.current_stack_frame().store(0, receiver);
+        }
+        for (i, arg) in bound.into_iter().enumerate() {
+            let value = match arg {
+                BoundArgument::Value(value) => value,
+                // the elements are still referenced from the stack while the list is allocated
+                BoundArgument::Rest(elements) => {
+                    self.alloc_object(Object::new_from_value(Value::List(elements)))
+                }
+            };
+            self.current_stack_frame().store(i + implicit, value);
+        }
+        self.stack_top -= n_values + 1; // consume arguments and function object
+        let stack_base = self.stack_top;
+        self.current_stack_frame().stack_base = stack_base;
+
+        match fun_info.address() {
+            FunctionAddress::Bytecode(pc) => {
+                self.pc = *pc;
+                Ok(())
+            }
+            FunctionAddress::Native(f) => {
+                let return_val = f(self)?;
+                let value = self.alloc_object(Object::new_from_value(return_val));
+                self.push(value)?;
+                let return_to_pc = self.current_stack_frame().return_pc;
+                self.pop_stackframe();
+                match return_to_pc {
+                    Some(return_to_pc) => {
+                        self.pc = return_to_pc;
+                        Ok(())
+                    }
+                    None => Err(self
+                        .runtime_error(RuntimeErrorKind::ReturnWithoutCall, "return without call")),
+                }
+            }
+        }
+    }
+
+    // calls `function` with `args` and runs it to completion. used by opcodes
+    // that call back into script code, such as loops over user-defined iterators.
+    pub fn call_function(
+        &mut self,
+        function: ObjectPtr,
+        args: &[ObjectPtr],
+    ) -> Result<ObjectPtr, RuntimeError> {
+        let saved_pc = self.pc;
+        let depth = self.stack_frame_top;
+
+        self.push(function)?;
+        for arg in args.iter() {
+            self.push(arg.clone())?;
+        }
+        self.opcode_call(args.len(), false, false)?;
+        // natives and classes without init are done already,
+        // while bytecode runs until its frame returns
+        while self.stack_frame_top > depth && !self.is_halted() {
+            self.step_code()?;
+        }
+
+        self.stack_top -= 1;
+        let result = self.stack[self.stack_top].clone();
+        self.pc = saved_pc;
+        Ok(result)
+    }
+
+    // calls method `name` of an instance without arguments
+    fn call_method(&mut self, receiver: ObjectPtr, name: &str) -> Result<ObjectPtr, RuntimeError> {
+        match find_method(&receiver, name) {
+            Some(method) => self.call_function(method, &[receiver]),
+            None => Err(self.runtime_error(
+                RuntimeErrorKind::AttributeNotFound,
+                format!(
+                    "{} has no method `{}`",
+                    describe_object(receiver.get().value()),
+                    name
+                ),
+            )),
+        }
+    }
+
+    // built-in values are iterated directly. instances are iterators if their class has
+    // `hasnext` and `next` methods, and are iterable if it has an `iter` method returning
+    // an iterator or a built-in iterable value.
+    fn opcode_get_iter(&mut self) -> Result<(), RuntimeError> {
+        let mut object = self.stack[self.stack_top - 1].clone();
+        let is_instance = |object: &ObjectPtr| matches!(object.get().value(), Value::Instance(_));
+        if is_instance(&object) && find_method(&object, "next").is_none() {
+            if find_method(&object, "iter").is_none() {
+                return Err(self.runtime_error(
+                    RuntimeErrorKind::NotIterable,
+                    format!(
+                        "{} is not iterable: it has no `iter` or `next` method",
+                        describe_object(object.get().value())
+                    ),
+                ));
+            }
+            object = self.call_method(object, "iter")?;
+            // keep the result rooted while a built-in iterator over it is allocated
+            self.stack[self.stack_top - 1] = object.clone();
+        }
+
+        let iterator = match object.get().value() {
+            Value::List(_) | Value::Tuple(_) => BuiltinIterator::Sequence {
+                sequence: object.clone(),
+                index: 0,
+            },
+            Value::Dict(map) => BuiltinIterator::Items {
+                items: map.entries().into_iter().map(|(k, _)| k).collect(),
+                index: 0,
+            },
+            Value::String(s) => BuiltinIterator::Chars {
+                chars: s.chars().collect(),
+                index: 0,
+            },
+            Value::Range(range) => BuiltinIterator::Range {
+                next: range.start(),
+                stop: range.stop(),
+                step: range.step(),
+            },
+            Value::Iterator(_) => return Ok(()),
+            Value::Instance(_) if find_method(&object, "next").is_some() => {
+                if find_method(&object, "hasnext").is_none() {
+                    return Err(self.runtime_error(
+                        RuntimeErrorKind::NotIterable,
+                        format!(
+                            "{} has `next` but no `hasnext` method",
+                            describe_object(object.get().value())
+                        ),
+                    ));
+                }
+                return Ok(());
+            }
+            v => {
+                return Err(self.runtime_error(
+                    RuntimeErrorKind::NotIterable,
+                    format!("{} is not iterable", describe_object(v)),
+                ))
+            }
+        };
+        self.stack[self.stack_top - 1] =
+            self.alloc_object(Object::new_from_value(Value::Iterator(iterator)));
+        Ok(())
+    }
+
+    // advances an iterator made by GetIter. returns None once it is exhausted.
+    fn iterator_next(
+        &mut self,
+        mut iterator: ObjectPtr,
+    ) -> Result<Option<ObjectPtr>, RuntimeError> {
+        if let Value::Instance(_) = iterator.get().value() {
+            let hasnext = self.call_method(iterator.clone(), "hasnext")?;
+            return match hasnext.get().value() {
+                Value::Boolean(true) => Ok(Some(self.call_method(iterator, "next")?)),
+                Value::Boolean(false) => Ok(None),
+                v => Err(self.runtime_error(
+                    RuntimeErrorKind::InvalidCondition,
+                    format!("`hasnext` must return a boolean, got {}", v.type_name()),
+                )),
+            };
+        }
+
+        let next = match iterator.get_mut().value_mut() {
+            Value::Iterator(BuiltinIterator::Sequence { sequence, index }) => {
+                let element = match sequence.get().value() {
+                    Value::List(elements) | Value::Tuple(elements) => elements.get(*index).cloned(),
+                    _ => unreachable!("sequence iterators only iterate lists and tuples"),
+                };
+                *index += 1;
+                return Ok(element);
+            }
+            Value::Iterator(BuiltinIterator::Items { items, index }) => {
+                let item = items.get(*index).cloned();
+                *index += 1;
+                return Ok(item);
+            }
+            Value::Iterator(BuiltinIterator::Chars { chars, index }) => {
+                let c = chars.get(*index).copied();
+                *index += 1;
+                c.map(|c| Value::String(c.to_string()))
+            }
+            Value::Iterator(BuiltinIterator::Range { next, stop, step }) => {
+                let in_range = match *step > 0 {
+                    true => *next < *stop,
+                    false => *next > *stop,
+                };
+                if in_range {
+                    let value = *next;
+                    *next = next.saturating_add(*step);
+                    Some(Value::Integer(value))
+                } else {
+                    None
+                }
+            }
+            _ => unreachable!("ForIter runs on values made by GetIter"),
+        };
+        // the iterator is still on the stack while the element is allocated
+        Ok(next.map(|value| self.alloc_object(Object::new_from_value(value))))
     }
 
-    pub fn register_native(&mut self, name: &str, f: &FunctionInfo) {
-        let fun_object =
-            self.alloc_object(Object::new_from_value(Value::Function(Box::new(f.clone()))));
+    fn opcode_get_attr(&mut self, name: &str) -> Result<(), RuntimeError> {
+        let object = self.stack[self.stack_top - 1].clone();
+
+        let result = match object.get().value() {
+            Value::Instance(instance) => {
+                let method = instance
+                    .class()
+                    .and_then(|class| match class.get().value() {
+                        Value::Class(class) => class.method(name),
+                        _ => None,
+                    });
+                match (instance.field(name), method) {
+                    (Some(field), _) => field,
+                    // the instance stays on the stack (and thus rooted) while the method is bound
+                    (None, Some(method)) => self.alloc_object(Object::new_from_value(
+                        Value::BoundMethod(BoundMethod::new(object.clone(), method)),
+                    )),
+                    (None, None) => {
+                        return Err(self.runtime_error(
+                            RuntimeErrorKind::AttributeNotFound,
+                            format!(
+                                "{} object has no attribute `{}`",
+                                instance.class_name(),
+                                name
+                            ),
+                        ))
+                    }
+                }
+            }
+            // methods looked up on the class itself are left unbound
+            Value::Class(class) => match class.method(name) {
+                Some(method) => method,
+                None => {
+                    return Err(self.runtime_error(
+                        RuntimeErrorKind::AttributeNotFound,
+                        format!("class {} has no attribute `{}`", class.name(), name),
+                    ))
+                }
+            },
+            v => {
+                return Err(self.runtime_error(
+                    RuntimeErrorKind::AttributeNotFound,
+                    format!("{} has no attribute `{}`", v.type_name(), name),
+                ))
+            }
+        };
+
+        self.stack[self.stack_top - 1] = result;
+        Ok(())
+    }
+
+    // replaces the receiver on top of the stack with `name` looked up from the parent
+    // of the class that defines the running method, bound to the receiver
+    fn opcode_get_super(&mut self, name: &str) -> Result<(), RuntimeError> {
+        let receiver = self.stack[self.stack_top - 1].clone();
+
+        let owner = match &self.current_stack_frame().function {
+            Some(function) => match function.get().value() {
+                Value::Function(f) => f.owner(),
+                _ => None,
+            },
+            None => None,
+        };
+        let owner = match owner {
+            Some(owner) => owner,
+            None => {
+                return Err(self.runtime_error(
+                    RuntimeErrorKind::AttributeNotFound,
+                    "`super` used outside of a method",
+                ))
+            }
+        };
+        let (class_name, parent) = match owner.get().value() {
+            Value::Class(class) => (class.name().to_string(), class.parent()),
+            _ => unreachable!("method owner must be a class"),
+        };
+        let method = parent.and_then(|parent| match parent.get().value() {
+            Value::Class(parent) => parent.method(name),
+            _ => None,
+        });
+
+        let method = match method {
+            Some(method) => method,
+            None => {
+                return Err(self.runtime_error(
+                    RuntimeErrorKind::AttributeNotFound,
+                    format!("no parent of {} has a method `{}`", class_name, name),
+                ))
+            }
+        };
+        // the receiver stays on the stack (and thus rooted) while the method is bound
+        let bound = BoundMethod::new(receiver, method);
+        self.stack[self.stack_top - 1] =
+            self.alloc_object(Object::new_from_value(Value::BoundMethod(bound)));
+        Ok(())
+    }
+
+    fn opcode_set_attr(&mut self, name: &str) -> Result<(), RuntimeError> {
+        let value = self.stack[self.stack_top - 1].clone();
+        let mut object = self.stack[self.stack_top - 2].clone();
+
+        if !matches!(object.get().value(), Value::Instance(_)) {
+            return Err(self.runtime_error(
+                RuntimeErrorKind::InvalidOperands,
+                format!(
+                    "cannot set attribute `{}` on {}",
+                    name,
+                    object.get().value().type_name()
+                ),
+            ));
+        }
+
+        // object and value stay on the stack (and thus rooted) while the key is allocated
+        let key = self.alloc_object(Object::const_string(name.to_string()));
+        if let Value::Instance(instance) = object.get_mut().value_mut() {
+            instance.set_field(key, value);
+        }
+        self.stack_top -= 2;
+        Ok(())
+    }
+
+    pub fn register_native(
+        &mut self,
+        name: &str,
+        address: NativeFunction,
+        signature: Signature,
+        defaults: Vec<Value>,
+    ) {
+        // each default stays on the stack (and thus rooted) until the function is allocated
+        let n_defaults = defaults.len();
+        for value in defaults {
+            self.stack[self.stack_top] = self.alloc_object(Object::new_from_value(value));
+            self.stack_top += 1;
+        }
+        let defaults = self.stack[self.stack_top - n_defaults..self.stack_top].to_vec();
+        let f = FunctionInfo::new(FunctionAddress::Native(address), signature, defaults);
+        let fun_object = self.alloc_object(Object::new_from_value(Value::Function(Box::new(f))));
+        self.stack_top -= n_defaults;
         self.globals.store(name, fun_object);
     }
 
-    pub fn step_code(&mut self) {
-        // early return if pc is larger than code size
-        if self.pc >= self.opcode.len() {
-            return;
+    // runs until Exit is executed or the end of code is reached.
+    // returns the exit code, or null if the code ran off its end.
+    pub fn run(&mut self) -> Result<Value, RuntimeError> {
+        while !self.is_halted() {
+            self.step_code()?;
+        }
+        Ok(match self.exit_code {
+            Some(exit_code) => Value::Integer(exit_code),
+            None => Value::Null,
+        })
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.exit_code.is_some() || self.pc >= self.opcode.len()
+    }
+
+    pub fn exit_code(&self) -> Option<i64> {
+        self.exit_code
+    }
+
+    pub fn step_code(&mut self) -> Result<(), RuntimeError> {
+        // early return if the program has finished
+        if self.is_halted() {
+            return Ok(());
         }
 
         // fetch opcode
@@ -197,64 +1092,93 @@ impl VM {
         // println!("pc: {:?}, executing {:?}", self.pc, op);
         match op {
             Opcode::ConstInt(const_value) => {
-                self.stack[self.stack_top] = self.alloc_object(Object::const_int(*const_value));
-                self.stack_top += 1;
+                let value = self.alloc_object(Object::const_int(*const_value));
+                self.push(value)?;
+            }
+            Opcode::ConstFloat(const_value) => {
+                let value = self.alloc_object(Object::const_float(*const_value));
+                self.push(value)?;
             }
             Opcode::ConstNull => {
-                self.stack[self.stack_top] = self.alloc_object(Object::const_null());
-                self.stack_top += 1;
+                let value = self.alloc_object(Object::const_null());
+                self.push(value)?;
+            }
+            Opcode::ConstBool(const_value) => {
+                let value = self.alloc_object(Object::const_bool(*const_value));
+                self.push(value)?;
             }
             Opcode::ConstString(const_value) => {
-                self.stack[self.stack_top] =
-                    self.alloc_object(Object::const_string(const_value.clone()));
-                self.stack_top += 1;
+                let value = self.alloc_object(Object::const_string(const_value.clone()));
+                self.push(value)?;
             }
             Opcode::Add2 => {
-                self.opcode_add();
+                self.opcode_add()?;
             }
             Opcode::Sub2 => {
-                self.opcode_arithmetic(op.clone());
+                self.opcode_arithmetic(op.clone())?;
             }
             Opcode::Mul2 => {
-                self.opcode_arithmetic(op.clone());
+                self.opcode_arithmetic(op.clone())?;
             }
             Opcode::Div2 => {
-                self.opcode_arithmetic(op.clone());
+                self.opcode_arithmetic(op.clone())?;
             }
             Opcode::Mod2 => {
-                self.opcode_arithmetic(op.clone());
+                self.opcode_arithmetic(op.clone())?;
             }
             Opcode::Eq2 => {
-                self.opcode_compare(op.clone());
+                self.opcode_compare(op.clone())?;
             }
             Opcode::Neq2 => {
-                self.opcode_compare(op.clone());
+                self.opcode_compare(op.clone())?;
             }
             Opcode::Lt2 => {
-                self.opcode_compare(op.clone());
+                self.opcode_compare(op.clone())?;
             }
             Opcode::Gt2 => {
-                self.opcode_compare(op.clone());
+                self.opcode_compare(op.clone())?;
             }
             Opcode::Le2 => {
-                self.opcode_compare(op.clone());
+                self.opcode_compare(op.clone())?;
             }
             Opcode::Ge2 => {
-                self.opcode_compare(op.clone());
+                self.opcode_compare(op.clone())?;
+            }
+            Opcode::Neg => {
+                self.opcode_neg()?;
+            }
+            Opcode::ToStr => {
+                let value = self.stack[self.stack_top - 1].clone();
+                let s = self.display_string(value.get().value())?;
+                self.stack[self.stack_top - 1] = self.alloc_object(Object::const_string(s));
+            }
+            Opcode::Not => {
+                let operand = self.stack[self.stack_top - 1].clone();
+                let result = !self.truthiness(&operand)?;
+                self.stack[self.stack_top - 1] = self.alloc_object(Object::const_bool(result));
             }
             Opcode::Exit => {
                 let exit_code = self.stack[self.stack_top - 1].clone();
                 self.stack_top -= 1;
                 match exit_code.get().value() {
                     Value::Integer(exit_code) => {
-                        std::process::exit(*exit_code as i32);
+                        self.exit_code = Some(*exit_code);
+                        return Ok(()); // stay on Exit
+                    }
+                    v => {
+                        return Err(self.runtime_error(
+                            RuntimeErrorKind::InvalidExitCode,
+                            format!("exit code must be an integer, got {}", v.type_name()),
+                        ))
                     }
-                    _ => panic!("invalid exit code"),
                 }
             }
             Opcode::Discard => {
                 self.stack_top -= 1;
             }
+            Opcode::Dup => {
+                self.push(self.stack[self.stack_top - 1].clone())?;
+            }
             Opcode::Store(address) => {
                 let value = self.stack[self.stack_top - 1].clone();
                 self.stack_top -= 1;
@@ -263,8 +1187,31 @@ impl VM {
             }
             Opcode::Load(address) => {
                 let value = self.current_stack_frame().load(*address);
-                self.stack[self.stack_top] = value;
-                self.stack_top += 1;
+                self.push(value)?;
+            }
+            Opcode::LoadUpvalue(index) => {
+                let upvalue = self.current_upvalue(*index);
+                let value = match upvalue.get().value() {
+                    Value::Upvalue(Upvalue::Open { frame, slot }) => {
+                        self.stack_frames[*frame].load(*slot)
+                    }
+                    Value::Upvalue(Upvalue::Closed(value)) => value.clone(),
+                    _ => unreachable!("closures only capture upvalues"),
+                };
+                self.push(value)?;
+            }
+            Opcode::StoreUpvalue(index) => {
+                let value = self.stack[self.stack_top - 1].clone();
+                self.stack_top -= 1;
+
+                let mut upvalue = self.current_upvalue(*index);
+                match upvalue.get_mut().value_mut() {
+                    Value::Upvalue(Upvalue::Open { frame, slot }) => {
+                        self.stack_frames[*frame].store(*slot, value)
+                    }
+                    Value::Upvalue(Upvalue::Closed(closed)) => *closed = value,
+                    _ => unreachable!("closures only capture upvalues"),
+                }
             }
             Opcode::StoreGlobal(address) => {
                 let value = self.stack[self.stack_top - 1].clone();
@@ -274,107 +1221,227 @@ impl VM {
             }
             Opcode::LoadGlobal(address) => {
                 let value = self.globals.load(address);
-                self.stack[self.stack_top] = value;
-                self.stack_top += 1;
+                self.push(value)?;
             }
             Opcode::JmpAlways(address) => {
                 self.pc = *address;
-                return; // avoid incrementing pc
+                return Ok(()); // avoid incrementing pc
             }
             Opcode::JmpIfTrue(address) => {
                 let cond = self.stack[self.stack_top - 1].clone();
                 self.stack_top -= 1;
 
-                match cond.get().value() {
-                    Value::Boolean(cond) => {
-                        if *cond {
-                            self.pc = *address;
-                            return; // avoid incrementing pc
-                        }
-                    }
-                    _ => panic!("invalid condition"),
+                if self.truthiness(&cond)? {
+                    self.pc = *address;
+                    return Ok(()); // avoid incrementing pc
                 }
             }
             Opcode::JmpIfFalse(address) => {
                 let cond = self.stack[self.stack_top - 1].clone();
                 self.stack_top -= 1;
 
-                match cond.get().value() {
-                    Value::Boolean(cond) => {
-                        if !*cond {
-                            self.pc = *address;
-                            return; // avoid incrementing pc
-                        }
+                if !self.truthiness(&cond)? {
+                    self.pc = *address;
+                    return Ok(()); // avoid incrementing pc
+                }
+            }
+            Opcode::BuildList(n_elements) => {
+                let elements = self.stack[self.stack_top - n_elements..self.stack_top].to_vec();
+                // elements stay on the stack (and thus rooted) until the list is allocated
+                let list = self.alloc_object(Object::new_from_value(Value::List(elements)));
+                self.stack_top -= n_elements;
+                self.push(list)?;
+            }
+            Opcode::BuildTuple(n_elements) => {
+                let elements = self.stack[self.stack_top - n_elements..self.stack_top].to_vec();
+                let tuple = self.alloc_object(Object::new_from_value(Value::Tuple(elements)));
+                self.stack_top -= n_elements;
+                self.push(tuple)?;
+            }
+            Opcode::BuildDict(n_pairs) => {
+                let base = self.stack_top - 2 * n_pairs;
+                let mut map = MyHashMap::new_default();
+                for i in 0..*n_pairs {
+                    let key = self.stack[base + 2 * i].clone();
+                    let value = self.stack[base + 2 * i + 1].clone();
+                    self.check_dict_key(key.get().value())?;
+                    map.put(key, value);
+                }
+                // keys and values stay on the stack (and thus rooted) until the dict is allocated
+                let dict = self.alloc_object(Object::new_from_value(Value::Dict(map)));
+                self.stack_top = base;
+                self.push(dict)?;
+            }
+            Opcode::GetIndex => {
+                self.opcode_get_index()?;
+            }
+            Opcode::GetIter => {
+                self.opcode_get_iter()?;
+            }
+            Opcode::ForIter(address) => {
+                let iterator = self.stack[self.stack_top - 1].clone();
+                match self.iterator_next(iterator)? {
+                    Some(value) => {
+                        self.push(value)?;
+                    }
+                    None => {
+                        self.stack_top -= 1;
+                        self.pc = *address;
+                        return Ok(()); // avoid incrementing pc
                     }
-                    _ => panic!("invalid condition"),
                 }
             }
+            Opcode::SetIndex => {
+                self.opcode_set_index()?;
+            }
+            Opcode::CloseUpvalues(slots) => {
+                self.close_upvalues_where(self.stack_frame_top, |slot| slots.contains(&slot));
+            }
             Opcode::Nop => {}
-            Opcode::CreateFunction(address, n_params) => {
-                let func_info = FunctionInfo::new(FunctionAddress::Bytecode(*address), *n_params);
+            Opcode::CreateFunction(address, signature) => {
+                let n_defaults = signature.n_defaults();
+                let defaults = self.stack[self.stack_top - n_defaults..self.stack_top].to_vec();
+                let func_info = FunctionInfo::new(
+                    FunctionAddress::Bytecode(*address),
+                    signature.clone(),
+                    defaults,
+                );
                 let func_value = Value::Function(Box::new(func_info));
                 let func_object = Object::new_from_value(func_value);
-                self.stack[self.stack_top] = self.alloc_object(func_object);
-                self.stack_top += 1;
+                // the defaults stay on the stack (and thus rooted) until the function is allocated
+                let func_object = self.alloc_object(func_object);
+                self.stack_top -= n_defaults;
+                self.push(func_object)?;
+            }
+            Opcode::MakeClosure(captures) => {
+                let function = self.stack[self.stack_top - 1].clone();
+                let upvalues = captures
+                    .iter()
+                    .map(|capture| match capture {
+                        Capture::Local(slot) => self.capture_local(*slot),
+                        Capture::Upvalue(index) => self.current_upvalue(*index),
+                    })
+                    .collect();
+                // the function stays on the stack and new upvalues are kept in open_upvalues,
+                // so everything is rooted while the closure is allocated
+                let closure = Closure::new(function, upvalues);
+                self.stack[self.stack_top - 1] =
+                    self.alloc_object(Object::new_from_value(Value::Closure(closure)));
             }
             Opcode::CallNoKw(n_args) => {
-                let fun_object = self.stack[self.stack_top - n_args - 1].clone();
-                let fun_value = fun_object.get().value();
-                let fun_info = match fun_value {
-                    Value::Function(fun_info) => fun_info,
-                    e => panic!("invalid function {:?}", e),
-                };
-
-                if fun_info.n_params() != *n_args {
-                    panic!("invalid number of arguments");
+                return self.opcode_call(*n_args, false, false);
+            }
+            Opcode::CallKw(n_args) => {
+                return self.opcode_call(*n_args, true, false);
+            }
+            Opcode::CallSpread(has_kwargs) => {
+                return self.opcode_call(1, *has_kwargs, true);
+            }
+            Opcode::ConcatLists(n_parts) => {
+                let base = self.stack_top - n_parts;
+                let mut elements = Vec::new();
+                for part in self.stack[base..self.stack_top].iter() {
+                    match part.get().value() {
+                        Value::List(items) | Value::Tuple(items) => {
+                            elements.extend(items.iter().cloned())
+                        }
+                        v => {
+                            return Err(self.runtime_error(
+                                RuntimeErrorKind::InvalidOperands,
+                                format!(
+                                    "only a list or tuple can be spread, got {}",
+                                    v.type_name()
+                                ),
+                            ))
+                        }
+                    }
                 }
+                // the parts stay on the stack (and thus rooted) until the list is allocated
+                let list = self.alloc_object(Object::new_from_value(Value::List(elements)));
+                self.stack_top = base;
+                self.push(list)?;
+            }
+            Opcode::CreateClass(name, n_methods) => {
+                let base = self.stack_top - 2 * n_methods - 1;
+                let parent = self.stack[base].clone();
+                let parent = match parent.get().value() {
+                    Value::Null => None,
+                    Value::Class(_) => Some(parent),
+                    v => {
+                        return Err(self.runtime_error(
+                            RuntimeErrorKind::InvalidOperands,
+                            format!("class {} cannot inherit from {}", name, v.type_name()),
+                        ))
+                    }
+                };
 
-                self.push_stackframe(self.pc + 1);
-                for i in (0..(*n_args)).rev() {
-                    // println!("storing arg {} in stack frame", i);
-                    let arg = self.stack[self.stack_top - 1].clone();
-                    self.stack_top -= 1;
-                    self.current_stack_frame().store(i, arg);
+                let mut methods = MyHashMap::new_default();
+                for i in 0..*n_methods {
+                    let method_name = self.stack[base + 1 + 2 * i].clone();
+                    let function = self.stack[base + 2 + 2 * i].clone();
+                    methods.put(method_name, function);
                 }
-                self.stack_top -= 1; // consume function object
-
-                match fun_info.address() {
-                    FunctionAddress::Bytecode(pc) => {
-                        self.pc = *pc;
-                        return; // avoid incrementing pc
-                    }
-                    FunctionAddress::Native(f) => {
-                        let return_val = f(self);
-                        self.stack[self.stack_top] =
-                            self.alloc_object(Object::new_from_value(return_val));
-                        self.stack_top += 1;
-                        let return_to_pc = self.current_stack_frame().return_pc;
-                        self.pop_stackframe();
-                        match return_to_pc {
-                            Some(return_to_pc) => {
-                                self.pc = return_to_pc;
-                                return; // avoid incrementing pc
-                            }
-                            None => panic!("return without call"),
-                        }
+                // the parent, names and functions stay on the stack (and thus rooted)
+                // until the class is allocated
+                let class = ClassObject::new(name.clone(), parent, methods);
+                let class =
+                    self.alloc_object(Object::new_from_value(Value::Class(Box::new(class))));
+                for i in 0..*n_methods {
+                    let method = self.stack[base + 2 + 2 * i].clone();
+                    let mut function = match method.get().value() {
+                        Value::Closure(closure) => closure.function(),
+                        _ => method,
+                    };
+                    if let Value::Function(f) = function.get_mut().value_mut() {
+                        f.set_owner(class.clone());
                     }
                 }
+                self.stack_top = base;
+                self.push(class)?;
+            }
+            Opcode::GetAttr(name) => {
+                self.opcode_get_attr(name)?;
+            }
+            Opcode::GetSuper(name) => {
+                self.opcode_get_super(name)?;
+            }
+            Opcode::SetAttr(name) => {
+                self.opcode_set_attr(name)?;
             }
             Opcode::Return => {
+                self.close_upvalues(self.stack_frame_top);
+                // constructors evaluate to the instance they initialized
+                let value = match self.current_stack_frame().constructing.clone() {
+                    Some(instance) => instance,
+                    None => self.stack[self.stack_top - 1].clone(),
+                };
+                self.stack_top = self.current_stack_frame().stack_base;
+                self.push(value)?;
                 let return_to_pc = self.current_stack_frame().return_pc;
                 self.pop_stackframe();
                 match return_to_pc {
                     Some(return_to_pc) => {
                         self.pc = return_to_pc;
-                        return; // avoid incrementing pc
+                        return Ok(()); // avoid incrementing pc
+                    }
+                    None => {
+                        return Err(self.runtime_error(
+                            RuntimeErrorKind::ReturnWithoutCall,
+                            "return without call",
+                        ))
                     }
-                    None => panic!("return without call"),
                 }
             }
-            _ => unimplemented!("opcode not implemented"),
+            _ => {
+                return Err(self.runtime_error(
+                    RuntimeErrorKind::UnimplementedOpcode,
+                    "opcode not implemented",
+                ))
+            }
         }
 
         self.pc += 1;
+        Ok(())
     }
 
     pub fn get_function_argument_by_index(&mut self, index: usize) -> ObjectPtr {
@@ -382,10 +1449,79 @@ impl VM {
     }
 }
 
+// value bound to a parameter slot by a call. the extra positional arguments
+// of a variadic function are collected into a list once the frame is set up.
+enum BoundArgument {
+    Value(ObjectPtr),
+    Rest(Vec<ObjectPtr>),
+}
+
+// method `name` of an instance's class (or its ancestors)
+fn find_method(object: &ObjectPtr, name: &str) -> Option<ObjectPtr> {
+    let class = match object.get().value() {
+        Value::Instance(instance) => instance.class()?,
+        _ => return None,
+    };
+    let method = match class.get().value() {
+        Value::Class(class) => class.method(name),
+        _ => None,
+    };
+    method
+}
+
+// how a value is named in error messages about its protocol methods
+fn describe_object(value: &Value) -> String {
+    match value {
+        Value::Instance(instance) => format!("{} object", instance.class_name()),
+        v => v.type_name().to_string(),
+    }
+}
+
+// frame and slot an open upvalue refers to
+fn open_location(upvalue: &ObjectPtr) -> Option<(usize, usize)> {
+    match upvalue.get().value() {
+        Value::Upvalue(Upvalue::Open { frame, slot }) => Some((*frame, *slot)),
+        _ => None,
+    }
+}
+
+// how a dictionary key is shown in error messages
+fn describe_key(key: &Value) -> String {
+    match key {
+        Value::String(s) => format!("{:?}", s),
+        Value::Integer(i) => i.to_string(),
+        Value::Float(f) => format!("{:?}", f),
+        Value::Boolean(b) => b.to_string(),
+        Value::Null => "null".to_string(),
+        Value::Tuple(elements) => {
+            let parts: Vec<String> = elements
+                .iter()
+                .map(|e| describe_key(e.get().value()))
+                .collect();
+            if parts.len() == 1 {
+                format!("({},)", parts[0])
+            } else {
+                format!("({})", parts.join(", "))
+            }
+        }
+        v => v.type_name().to_string(),
+    }
+}
+
 #[derive(Debug)]
 struct LinearMemory {
     memory: Vec<ObjectPtr>,
     return_pc: Option<usize>,
+    // height of the operand stack when the frame was entered. values the function
+    // left on the stack (e.g. the iterator of a loop it returned from) are dropped
+    // down to this on return.
+    stack_base: usize,
+    // function running in this frame
+    function: Option<ObjectPtr>,
+    // closure holding the upvalues of the function, if it captured any
+    closure: Option<ObjectPtr>,
+    // instance being initialized when this is a constructor frame
+    constructing: Option<ObjectPtr>,
 
     invalid_obj: ObjectPtr,
 }
@@ -395,6 +1531,10 @@ impl LinearMemory {
         LinearMemory {
             memory: Vec::new(),
             return_pc: None,
+            stack_base: 0,
+            function: None,
+            closure: None,
+            constructing: None,
             invalid_obj,
         }
     }
@@ -403,6 +1543,10 @@ impl LinearMemory {
         LinearMemory {
             memory: Vec::new(),
             return_pc: Some(return_pc),
+            stack_base: 0,
+            function: None,
+            closure: None,
+            constructing: None,
             invalid_obj,
         }
     }
@@ -424,7 +1568,13 @@ impl LinearMemory {
     }
 
     pub fn collect_objptr(&mut self) -> Vec<ObjectPtr> {
-        self.memory.clone()
+        // invalid_obj is handed out for unset slots, so it must stay alive too
+        let mut pointers = self.memory.clone();
+        pointers.push(self.invalid_obj.clone());
+        pointers.extend(self.function.clone());
+        pointers.extend(self.closure.clone());
+        pointers.extend(self.constructing.clone());
+        pointers
     }
 }
 
@@ -453,6 +1603,8 @@ impl NameMemory {
     }
 
     pub fn collect_objptr(&mut self) -> Vec<ObjectPtr> {
-        self.memory.values().cloned().collect()
+        let mut pointers: Vec<ObjectPtr> = self.memory.values().cloned().collect();
+        pointers.push(self.invalid_obj.clone());
+        pointers
     }
 }
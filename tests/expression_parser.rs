@@ -0,0 +1,194 @@
+use factory::ast::{
+    Argument, BinaryOperator, Expression, ExpressionKind, LiteralExpression, StringPart,
+    UnaryOperator,
+};
+use factory::parser::{expression, BINARY_OPERATORS};
+use nom_locate::LocatedSpan;
+
+// the expression written with every operation in explicit parentheses
+fn grouped(expr: &Expression) -> String {
+    match expr.kind() {
+        ExpressionKind::Binary(bin) => {
+            let op = match bin.op() {
+                BinaryOperator::Plus => "+",
+                BinaryOperator::Minus => "-",
+                BinaryOperator::Times => "*",
+                BinaryOperator::Divide => "/",
+                BinaryOperator::Modulo => "%",
+                BinaryOperator::Eq => "==",
+                BinaryOperator::Neq => "!=",
+                BinaryOperator::Lt => "<",
+                BinaryOperator::Le => "<=",
+                BinaryOperator::Gt => ">",
+                BinaryOperator::Ge => ">=",
+                BinaryOperator::And => "and",
+                BinaryOperator::Or => "or",
+            };
+            format!("({} {} {})", grouped(bin.left()), op, grouped(bin.right()))
+        }
+        ExpressionKind::Unary(unary) => match unary.op() {
+            UnaryOperator::Neg => format!("(-{})", grouped(unary.operand())),
+            UnaryOperator::Not => format!("(not {})", grouped(unary.operand())),
+        },
+        ExpressionKind::Literal(LiteralExpression::Integer(i)) => i.to_string(),
+        ExpressionKind::Literal(LiteralExpression::Boolean(b)) => b.to_string(),
+        ExpressionKind::Literal(LiteralExpression::String(s)) => format!("{:?}", s),
+        ExpressionKind::InterpolatedString(parts) => {
+            let parts: Vec<String> = parts
+                .iter()
+                .map(|part| match part {
+                    StringPart::Text(s) => format!("{:?}", s),
+                    StringPart::Interpolation(e) => format!("str({})", grouped(e)),
+                })
+                .collect();
+            format!("({})", parts.join(" ++ "))
+        }
+        ExpressionKind::Name(name) => name.get_name().to_string(),
+        ExpressionKind::FunCall(call) => {
+            let args: Vec<String> = call
+                .args()
+                .iter()
+                .map(|arg| match arg {
+                    Argument::Value(e) => grouped(e),
+                    Argument::Spread(e) => format!("*{}", grouped(e)),
+                })
+                .collect();
+            format!("{}({})", grouped(call.callee()), args.join(", "))
+        }
+        ExpressionKind::Index(index) => {
+            format!("{}[{}]", grouped(index.callee()), grouped(index.arg()))
+        }
+        ExpressionKind::Attribute(attr) => format!("{}.{}", grouped(attr.object()), attr.name()),
+        other => panic!("unexpected expression in test: {:?}", other),
+    }
+}
+
+// parses the whole source as one expression
+fn parse(source: &str) -> String {
+    match expression(LocatedSpan::new(source)) {
+        Ok((rest, expr)) if rest.fragment().is_empty() => grouped(&expr),
+        Ok((rest, _)) => panic!("`{}` left unparsed in `{}`", rest.fragment(), source),
+        Err(e) => panic!("`{}` failed to parse: {:?}", source, e),
+    }
+}
+
+#[test]
+fn subtraction_and_division_are_left_associative() {
+    assert_eq!(parse("10 - 2 - 3"), "((10 - 2) - 3)");
+    assert_eq!(parse("100 / 10 / 5"), "((100 / 10) / 5)");
+    assert_eq!(parse("a % b % c"), "((a % b) % c)");
+    assert_eq!(parse("1 - 2 + 3 - 4"), "(((1 - 2) + 3) - 4)");
+    assert_eq!(parse("a * b / c * d"), "(((a * b) / c) * d)");
+}
+
+#[test]
+fn products_bind_tighter_than_sums() {
+    assert_eq!(parse("1 + 2 * 3"), "(1 + (2 * 3))");
+    assert_eq!(parse("1 * 2 + 3"), "((1 * 2) + 3)");
+    assert_eq!(parse("a - b * c - d"), "((a - (b * c)) - d)");
+    assert_eq!(parse("(1 + 2) * 3"), "((1 + 2) * 3)");
+}
+
+#[test]
+fn every_comparison_operator_parses() {
+    assert_eq!(parse("a == b"), "(a == b)");
+    assert_eq!(parse("a != b"), "(a != b)");
+    assert_eq!(parse("a < b"), "(a < b)");
+    assert_eq!(parse("a <= b"), "(a <= b)");
+    assert_eq!(parse("a > b"), "(a > b)");
+    assert_eq!(parse("a >= b"), "(a >= b)");
+    assert_eq!(parse("a+1<=b*2"), "((a + 1) <= (b * 2))");
+}
+
+#[test]
+fn comparisons_bind_looser_than_arithmetic() {
+    assert_eq!(parse("a + 1 < b - 1"), "((a + 1) < (b - 1))");
+    assert_eq!(parse("a < b == c"), "((a < b) == c)");
+}
+
+#[test]
+fn logical_operators() {
+    assert_eq!(parse("a or b and c"), "(a or (b and c))");
+    assert_eq!(parse("a and b or c and d"), "((a and b) or (c and d))");
+    assert_eq!(parse("a and b and c"), "((a and b) and c)");
+    assert_eq!(parse("a < b and b < c"), "((a < b) and (b < c))");
+    assert_eq!(parse("not a == b"), "(not (a == b))");
+    assert_eq!(parse("not a and b"), "((not a) and b)");
+    assert_eq!(parse("not not a"), "(not (not a))");
+}
+
+#[test]
+fn unary_minus_binds_tightest() {
+    assert_eq!(parse("-a * b"), "((-a) * b)");
+    assert_eq!(parse("a - -b"), "(a - (-b))");
+    assert_eq!(parse("-f(x)[0]"), "(-f(x)[0])");
+    assert_eq!(parse("2 * -3"), "(2 * (-3))");
+}
+
+#[test]
+fn postfix_operations_bind_tighter_than_operators() {
+    assert_eq!(parse("f(a - b - c) * x.y"), "(f(((a - b) - c)) * x.y)");
+    assert_eq!(parse("xs[i + 1] - xs[i]"), "(xs[(i + 1)] - xs[i])");
+}
+
+#[test]
+fn word_operators_need_word_boundaries() {
+    assert_eq!(parse("android"), "android");
+    assert_eq!(parse("a or origin"), "(a or origin)");
+    assert_eq!(parse("nothing"), "nothing");
+}
+
+#[test]
+fn operator_must_be_followed_by_an_operand() {
+    assert!(expression(LocatedSpan::new("1 +")).is_err());
+    assert!(expression(LocatedSpan::new("a and")).is_err());
+    assert!(expression(LocatedSpan::new("-")).is_err());
+}
+
+#[test]
+fn no_operator_token_is_shadowed_by_an_earlier_one() {
+    for (i, later) in BINARY_OPERATORS.iter().enumerate() {
+        for earlier in &BINARY_OPERATORS[..i] {
+            assert!(
+                !later.token.starts_with(earlier.token),
+                "`{}` is tried before `{}` and would match its prefix",
+                earlier.token,
+                later.token
+            );
+        }
+    }
+}
+
+#[test]
+fn string_escapes() {
+    assert_eq!(parse(r#""""#), r#""""#);
+    assert_eq!(parse(r#""a\tb\n""#), r#""a\tb\n""#);
+    assert_eq!(parse(r#""\"\\\{\}""#), r#""\"\\{}""#);
+    assert_eq!(parse(r#""\u{41}\u{e9}""#), r#""Aé""#);
+    assert!(expression(LocatedSpan::new(r#""\q""#)).is_err());
+}
+
+#[test]
+fn string_interpolation() {
+    assert_eq!(parse(r#""n = {n}""#), r#"("n = " ++ str(n))"#);
+    assert_eq!(
+        parse(r#""{a}-{b + 1}!""#),
+        r#"(str(a) ++ "-" ++ str((b + 1)) ++ "!")"#
+    );
+    assert!(expression(LocatedSpan::new(r#""{}""#)).is_err());
+}
+
+#[test]
+fn triple_quoted_strings_span_lines() {
+    assert_eq!(parse("\"\"\"a\n\"b\"\n\"\"\""), r#""a\n\"b\"\n""#);
+    assert!(expression(LocatedSpan::new("\"a\nb\"")).is_err());
+}
+
+#[test]
+fn identifiers_allow_underscores_and_keyword_prefixes() {
+    assert_eq!(parse("line_count + _tmp"), "(line_count + _tmp)");
+    assert_eq!(parse("ifx - endpoint"), "(ifx - endpoint)");
+    assert_eq!(parse("x2.y_3"), "x2.y_3");
+    assert!(expression(LocatedSpan::new("3abc")).is_err());
+    assert!(expression(LocatedSpan::new("end")).is_err());
+}
@@ -0,0 +1,556 @@
+use factory::compiler::{Compiler, Program};
+use factory::diagnostics::Diagnostic;
+use factory::extension::{basic::BasicFunctions, register_native};
+use factory::object::{Signature, Value};
+use factory::parser::program;
+use factory::vm::{RuntimeError, RuntimeErrorKind, VM};
+use nom::Finish;
+use nom_locate::LocatedSpan;
+
+// `check(condition)` fails the script when the condition does not hold
+fn check_impl(vm: &mut VM) -> Result<Value, RuntimeError> {
+    let condition = vm.get_function_argument_by_index(0);
+    if matches!(condition.get().value(), Value::Boolean(true)) {
+        Ok(Value::Null)
+    } else {
+        Err(vm.runtime_error(RuntimeErrorKind::InvalidOperands, "check failed"))
+    }
+}
+
+fn new_vm() -> VM {
+    let mut vm = VM::new(1024);
+    register_native(&mut vm, &BasicFunctions::default());
+    vm.register_native(
+        "check",
+        check_impl,
+        Signature::positional(vec!["condition".to_string()]),
+        vec![],
+    );
+    vm
+}
+
+// parses, compiles and links a script. errors are returned as their messages.
+fn compile(source: &str) -> Result<Program, String> {
+    let (_, statements) = program(LocatedSpan::new(source))
+        .finish()
+        .map_err(|e| Diagnostic::from_parse_error(&e).to_string())?;
+    let mut compiler = Compiler::new();
+    compiler.compile_top(&statements).map_err(|errors| {
+        errors
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    })?;
+    compiler.link().map_err(|e| e.to_string())
+}
+
+fn run_in(vm: &mut VM, source: &str) -> Result<(), String> {
+    vm.set_program(compile(source)?);
+    vm.run().map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn run(source: &str) -> Result<(), String> {
+    run_in(&mut new_vm(), source)
+}
+
+fn assert_runs(source: &str) {
+    if let Err(e) = run(source) {
+        panic!("script failed: {}\n{}", e, source);
+    }
+}
+
+fn assert_fails_with(source: &str, message: &str) {
+    match run(source) {
+        Ok(()) => panic!("script succeeded, expected `{}`\n{}", message, source),
+        Err(e) => assert!(e.contains(message), "expected `{}`, got: {}", message, e),
+    }
+}
+
+#[test]
+fn integer_overflow_is_a_runtime_error() {
+    assert_fails_with("x = 9223372036854775807 + 1", "integer overflow");
+    assert_fails_with("x = 2 * 4611686018427387904", "integer overflow");
+    assert_fails_with("x = (0 - 9223372036854775807) - 2", "integer overflow");
+    assert_fails_with(
+        "x = ((0 - 9223372036854775807) - 1) / (0 - 1)",
+        "integer overflow",
+    );
+    assert_fails_with(
+        "x = ((0 - 9223372036854775807) - 1) % (0 - 1)",
+        "integer overflow",
+    );
+    assert_fails_with("x = -((0 - 9223372036854775807) - 1)", "integer overflow");
+    assert_runs("check(9223372036854775806 + 1 == 9223372036854775807)");
+}
+
+#[test]
+fn deep_recursion_is_a_stack_overflow() {
+    assert_fails_with(
+        "def deep(n) do if n > 0 do return 1 + deep(n - 1) end else do return 0 end end deep(2000)",
+        "stack overflow",
+    );
+    assert_fails_with(
+        "def deep(n) do if n > 0 do deep(n - 1) end else do return 0 end end deep(5000)",
+        "stack overflow",
+    );
+    assert_runs("def deep(n) do if n > 0 do return 1 + deep(n - 1) end else do return 0 end end check(deep(100) == 100)");
+}
+
+#[test]
+fn vm_can_be_reused_after_a_failed_run() {
+    let mut vm = new_vm();
+    let deep = "def deep(n) do if n > 0 do return 1 + deep(n - 1) end else do return 0 end end";
+    assert!(run_in(&mut vm, &format!("{}\ndeep(2000)", deep)).is_err());
+
+    if let Err(e) = run_in(&mut vm, &format!("{}\ncheck(deep(100) == 100)", deep)) {
+        panic!("script failed: {}", e);
+    }
+}
+
+#[test]
+fn hash_bracket_starts_a_block_comment_only_before_whitespace() {
+    assert_runs("#[note] a line comment\nx = 1\ncheck(x == 1)");
+    assert_runs("#[ a block\n  #[ nested ]#\n  #[note] not an opener\n]#\ncheck(1 == 1)");
+    assert_runs("x = 1 #[\n  2\n]# + 2\ncheck(x == 3)");
+    assert!(compile("#[ unterminated\nx = 1").is_err());
+}
+
+#[test]
+fn integer_literal_must_fit_in_an_int() {
+    assert_runs("check(9223372036854775807 - 1 == 9223372036854775806)");
+    assert_eq!(
+        compile("x = 1 + 99999999999999999999").err().unwrap(),
+        "integer literal `99999999999999999999` is too large at 1:9"
+    );
+}
+
+#[test]
+fn self_containing_containers_are_displayed_once() {
+    assert_runs(
+        r#"
+a = [0, 0, 0, 0]
+a[0] = a
+a[1] = a
+a[2] = a
+a[3] = a
+check(str(a) == "[[...], [...], [...], [...]]")
+d = {1: 2}
+d[3] = d
+check(str(d) == "\{1: 2, 3: \{...}}")
+b = [1]
+check(str([b, b]) == "[[1], [1]]")
+check(str((b, (b,))) == "([1], ([1],))")
+"#,
+    );
+}
+
+#[test]
+fn any_values_can_be_compared_for_equality() {
+    assert_runs(
+        r#"
+def f(x) do return x end
+def g(x) do return x end
+check(f == f)
+check(f != g)
+check(f != 1)
+check([1, [2, "a"]] == [1, [2, "a"]])
+check([1, 2] != [1, 3])
+check([1] != (1,))
+check({"a": [1]} == {"a": [1]})
+check({"a": 1} != {"a": 2})
+"#,
+    );
+    assert_fails_with("x = [1] < [2]", "invalid operands for comparison");
+}
+
+#[test]
+fn containers_holding_themselves_can_be_compared() {
+    assert_runs(
+        r#"
+a = [1, 0]
+a[1] = a
+b = [1, 0]
+b[1] = b
+check(a == b)
+c = [2, 0]
+c[1] = c
+check(a != c)
+d = [a, a, a, a]
+d[0] = d
+d[1] = d
+e = [b, b, b, b]
+e[0] = e
+e[1] = e
+check(d == e)
+x = {"k": 0}
+x["k"] = x
+y = {"k": 0}
+y["k"] = y
+check(x == y)
+"#,
+    );
+}
+
+#[test]
+fn ints_and_floats_are_equal_only_when_exact() {
+    assert_runs(
+        r#"
+check(1 == 1.0)
+check(1.5 != 1)
+check(9007199254740993 != 9007199254740992.0)
+check(9007199254740992 == 9007199254740992.0)
+d = {9007199254740992.0: "float"}
+check(d[9007199254740992] == "float")
+no = 1 == 0
+check(has(d, 9007199254740993) == no)
+"#,
+    );
+}
+
+#[test]
+fn classes_construct_instances_with_fields_and_methods() {
+    assert_runs(
+        r#"
+class Counter do
+  def init(start, step) do
+    self.count = start
+    self.step = step
+  end
+
+  def tick() do
+    self.count = self.count + self.step
+    return self.count
+  end
+end
+
+c = Counter(10, 1)
+check(c.count == 10)
+check(c.tick() == 11)
+check(c.count == 11)
+d = Counter(0, 5)
+check(d.tick() == 5)
+check(c.count == 11)
+
+c.label = "outside"
+check(c.label == "outside")
+c.count = 100
+check(c.tick() == 101)
+
+# a method read off an instance stays bound to it
+tick = d.tick
+check(tick() == 10)
+check(d.count == 10)
+
+# read off the class, it is unbound and takes the receiver explicitly
+check(Counter.tick(c) == 102)
+"#,
+    );
+}
+
+#[test]
+fn class_error_paths() {
+    let point = "class Point do\n  def init(x) do\n    self.x = x\n  end\nend\n";
+    assert_fails_with(
+        &format!("{}p = Point(1)\nx = p.y", point),
+        "Point object has no attribute `y`",
+    );
+    assert_fails_with(
+        &format!("{}x = Point.missing", point),
+        "class Point has no attribute `missing`",
+    );
+    assert_fails_with(
+        &format!("{}p = Point(1)\np()", point),
+        "instance is not callable",
+    );
+    assert_fails_with(&format!("{}p = Point()", point), "missing argument `x`");
+    assert_fails_with(
+        "class Empty do\nend\ne = Empty(1)",
+        "Empty has no init method and takes no arguments, got 1",
+    );
+}
+
+#[test]
+fn subclasses_inherit_and_override_methods() {
+    assert_runs(
+        r#"
+class Shape do
+  def init(name) do
+    self.name = name
+  end
+
+  def area() do
+    return 0
+  end
+
+  def describe() do
+    return self.name + " with area " + str(self.area())
+  end
+end
+
+class Rect(Shape) do
+  def init(w, h) do
+    super.init("rect")
+    self.w = w
+    self.h = h
+  end
+
+  def area() do
+    return self.w * self.h
+  end
+end
+
+class Square(Rect) do
+  def init(side) do
+    super.init(side, side)
+    self.name = "square"
+  end
+
+  def describe() do
+    return "a " + super.describe()
+  end
+end
+
+class Blob(Shape) do
+end
+
+r = Rect(2, 3)
+check(r.describe() == "rect with area 6")
+s = Square(4)
+check(s.area() == 16)
+check(s.describe() == "a square with area 16")
+b = Blob("blob")
+check(b.describe() == "blob with area 0")
+
+check(isinstance(s, Square))
+check(isinstance(s, Rect))
+check(isinstance(s, Shape))
+no = 1 == 0
+check(isinstance(r, Square) == no)
+check(isinstance(b, Rect) == no)
+check(isinstance(1, Shape) == no)
+"#,
+    );
+}
+
+#[test]
+fn inheritance_error_paths() {
+    assert_fails_with(
+        "class A do\n  def f() do\n    return super.f()\n  end\nend\nx = A().f()",
+        "no parent of A has a method `f`",
+    );
+    assert_fails_with(
+        "p = 1\nclass A(p) do\nend",
+        "class A cannot inherit from int",
+    );
+    assert_fails_with(
+        "class A do\nend\nx = isinstance(A(), 1)",
+        "expected a class, got int",
+    );
+}
+
+#[test]
+fn closures_keep_captured_variables_after_their_frame_returns() {
+    assert_runs(
+        r#"
+def counter() do
+  count = 0
+  def step() do
+    count = count + 1
+    return count
+  end
+  return step
+end
+a = counter()
+b = counter()
+check(a() == 1)
+check(a() == 2)
+check(b() == 1)
+check(a() == 3)
+"#,
+    );
+}
+
+#[test]
+fn closures_share_a_captured_variable() {
+    assert_runs(
+        r#"
+def cell(value) do
+  def get() do
+    return value
+  end
+  def set(v) do
+    value = v
+    return v
+  end
+  check(get() == value)
+  return (get, set)
+end
+pair = cell(1)
+get = pair[0]
+set = pair[1]
+set(42)
+check(get() == 42)
+other = cell(7)
+check(other[0]() == 7)
+check(get() == 42)
+"#,
+    );
+}
+
+#[test]
+fn loops_capture_each_iteration_separately() {
+    assert_runs(
+        r#"
+def make() do
+  total = 0
+  fs = [0, 0, 0]
+  i = 0
+  while i < 3 do
+    j = i * 10
+    def f() do
+      return j + total
+    end
+    fs[i] = f
+    i = i + 1
+  end
+  total = 100
+  return fs
+end
+fs = make()
+check(fs[0]() == 100)
+check(fs[1]() == 110)
+check(fs[2]() == 120)
+
+def each() do
+  total = 0
+  gs = [0, 0, 0]
+  for i in range(3) do
+    j = i * 10
+    gs[i] = fn() do return i + (j + total) end
+  end
+  total = 100
+  return gs
+end
+gs = each()
+check(gs[0]() == 100)
+check(gs[1]() == 111)
+check(gs[2]() == 122)
+
+def skipping() do
+  fs = [0, 0, 0, 0]
+  n = 0
+  while n < 4 do
+    k = n
+    n = n + 1
+    if k == 1 do
+      continue
+    end
+    fs[k] = fn() do return k end
+  end
+  return fs
+end
+hs = skipping()
+check(hs[0]() == 0)
+check(hs[1] == 0)
+check(hs[2]() == 2)
+check(hs[3]() == 3)
+"#,
+    );
+}
+
+#[test]
+fn captured_values_survive_garbage_collection() {
+    assert_runs(
+        r#"
+def keep() do
+  items = [1, [2, 3], "four"]
+  def get() do
+    return items
+  end
+  return get
+end
+get = keep()
+i = 0
+while i < 2000 do
+  garbage = [i, [i], str(i)]
+  i = i + 1
+end
+check(get() == [1, [2, 3], "four"])
+"#,
+    );
+}
+
+#[test]
+fn bare_return_can_start_a_block() {
+    assert_runs(
+        r#"
+def f() do return end
+check(str(f()) == "null")
+def g(x) do
+  if x do
+    return
+  end
+  return 1
+end
+check(str(g(1 == 1)) == "null")
+check(g(1 == 0) == 1)
+"#,
+    );
+}
+
+#[test]
+fn parameters_must_be_unique_and_follow_a_bare_star() {
+    assert_eq!(
+        compile("def f(a, b, a) do end").unwrap_err(),
+        "parameter `a` is declared more than once at 1:13"
+    );
+    assert_eq!(
+        compile("def f(a, *a) do end").unwrap_err(),
+        "parameter `a` is declared more than once at 1:11"
+    );
+    assert_eq!(
+        compile("f = fn(x, *, x=1) do end").unwrap_err(),
+        "parameter `x` is declared more than once at 1:14"
+    );
+    assert_eq!(
+        compile("def f(a, *) do end").unwrap_err(),
+        "`*` must be followed by a keyword-only parameter at 1:10"
+    );
+    assert!(compile("def f(a, *, b=1) do end").is_ok());
+    assert!(compile("def f(a, *rest) do end").is_ok());
+}
+
+#[test]
+fn repeated_keyword_argument_is_a_syntax_error() {
+    assert_eq!(
+        compile("def f(a, b) do end\nf(a=1, b=2, a=3)").unwrap_err(),
+        "keyword argument `a` is given more than once at 2:13"
+    );
+    assert!(compile("def f(a, b) do end\nf(a=1, b=2)").is_ok());
+}
+
+#[test]
+fn super_is_only_allowed_directly_in_methods() {
+    let base = "class A do\n  def name() do return \"a\" end\nend\n";
+    let error = compile(&format!(
+        "{}class B(A) do\n  def names() do\n    return fn() do return super.name() end\n  end\nend",
+        base
+    ))
+    .unwrap_err();
+    assert!(
+        error.contains("`super` used outside of a method"),
+        "{}",
+        error
+    );
+    assert!(compile(&format!(
+        "{}class B(A) do\n  def names() do\n    def inner() do return super.name() end\n  end\nend",
+        base
+    ))
+    .is_err());
+    assert!(compile("x = super.name").is_err());
+    assert_runs(&format!(
+        "{}class B(A) do\n  def name() do return super.name() + \"b\" end\nend\ncheck(B().name() == \"ab\")",
+        base
+    ));
+}